@@ -1,15 +1,41 @@
 use crate::client::{Answer, Command, PlayerId};
-use crate::{AlgorithmConfig, Position};
+use crate::{AlgorithmConfig, MoveDirection, Position};
+pub(crate) use algorithm1::FloodConfig;
+pub(crate) use algorithm2::TaintConfig;
+pub(crate) use algorithm3::HybridConfig;
+pub(crate) use algorithm4::RankedConfig;
+pub(crate) use algorithm5::SearchConfig;
+pub(crate) use algorithm8::MctsConfig;
+use bitboard::Bitboard;
 use core::option::Option;
 use core::option::Option::{None, Some};
-use rand::rngs::ThreadRng;
-use std::collections::HashMap;
+use helper::{has_neighbour_head, iter_directions, move_by_direction};
+use log::warn;
+use opponent::OpponentModel;
+use ordered_float::OrderedFloat;
+use rand::rngs::StdRng;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
 
 mod algorithm1;
 mod algorithm2;
 mod algorithm3;
 mod algorithm4;
-mod helper;
+mod algorithm5;
+mod algorithm6;
+mod algorithm8;
+pub(crate) mod bitboard;
+pub(crate) mod chamber;
+mod endgame;
+pub(crate) mod frame;
+pub(crate) mod helper;
+pub(crate) mod opponent;
+#[cfg(test)]
+mod regressions;
+pub(crate) mod render;
+#[cfg(test)]
+mod scenario;
 
 #[derive(Default, Clone)]
 pub struct State {
@@ -17,69 +43,725 @@ pub struct State {
     my_position: Position,
     /// PlayerId per field
     field_occupation: ndarray::Array2<Option<PlayerId>>,
-    player_heads: HashMap<PlayerId, Position>,
+    /// All fields with `Some` player in `field_occupation`
+    occupied: Bitboard,
+    player_heads: BTreeMap<PlayerId, Position>,
     game_size: Position,
+    /// What we learned about each living opponent in the current game
+    opponents: BTreeMap<PlayerId, OpponentModel>,
+    /// Names of all players of the current game
+    player_names: BTreeMap<PlayerId, String>,
+    /// Moves received since the last tick, observed once the tick is complete
+    tick_moves: Vec<Undo>,
+    /// Number of ticks in the current game so far
+    tick: u32,
 }
 
 impl State {
     pub fn update_from_answer(&mut self, answer: &Answer) {
         match answer {
             Answer::Pos(p, position) => {
-                if *p == self.my_id {
-                    self.my_position = position.clone();
+                if position.x >= self.game_size.x || position.y >= self.game_size.y {
+                    warn!("Ignoring position {:?} outside of the board", position);
+                    return;
                 }
-                self.player_heads.insert(p.clone(), position.clone());
-                self.field_occupation[position.as_dim()] = Some(p.clone());
+                let undo = self.make_moves(&[(*p, position.clone())]);
+                self.tick_moves.push(undo);
             }
+            Answer::Tick => {
+                self.observe_tick_moves();
+                self.tick += 1;
+            }
+            Answer::Win(..) | Answer::Lose(..) => self.observe_tick_moves(),
             Answer::Game(size, my_id) => {
-                self.my_id = my_id.clone();
+                self.tick_moves.clear();
+                self.tick = 0;
+                self.my_id = *my_id;
                 self.field_occupation = ndarray::Array2::from_elem(size.as_dim(), None);
+                self.occupied = Bitboard::new(size);
                 self.game_size = size.clone();
                 self.player_heads.clear();
+                self.opponents.clear();
+                self.player_names.clear();
             }
             Answer::Die(dead_players) => {
-                for field in self.field_occupation.iter_mut() {
+                self.observe_tick_moves();
+                for (index, field) in self.field_occupation.indexed_iter_mut() {
                     if field.is_some_and(|occupied_by| dead_players.contains(&occupied_by)) {
                         *field = None;
+                        self.occupied.unset(&Position {
+                            x: index.0 as u32,
+                            y: index.1 as u32,
+                        });
                     }
                 }
                 for p in dead_players {
                     self.player_heads.remove(p);
+                    self.opponents.remove(p);
                 }
             }
+            Answer::Player(p, name) => {
+                self.player_names.insert(*p, name.clone());
+            }
             _ => {}
         }
     }
 
+    /// Observe the opponents' moves of the last tick against the board before the tick, so that
+    /// no opponent sees the moves of the others in the same tick.
+    fn observe_tick_moves(&mut self) {
+        let undos = std::mem::take(&mut self.tick_moves);
+        let moves: Vec<(PlayerId, Position)> = undos
+            .iter()
+            .flat_map(|undo| undo.changes.iter())
+            .map(|(player, _previous_head, field, _previous_occupation)| (*player, field.clone()))
+            .collect();
+        for undo in undos.into_iter().rev() {
+            self.unmake_moves(undo);
+        }
+        let observations: Vec<_> = moves
+            .iter()
+            .filter(|(player, _to)| *player != self.my_id)
+            .filter_map(|(player, to)| {
+                opponent::observe_move(self, *player, to).map(|observation| (*player, observation))
+            })
+            .collect();
+        let _ = self.make_moves(&moves);
+        for (player, observation) in observations {
+            self.opponents
+                .entry(player)
+                .or_default()
+                .record(observation);
+        }
+    }
+
+    pub fn my_id(&self) -> PlayerId {
+        self.my_id
+    }
+
+    /// Number of ticks in the current game so far, i.e. the ticks survived by the living players
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    /// All players which are still alive, including us
+    pub fn living_players(&self) -> impl Iterator<Item = PlayerId> + '_ {
+        self.player_heads.keys().copied()
+    }
+
+    pub fn player_head(&self, player: PlayerId) -> Option<&Position> {
+        self.player_heads.get(&player)
+    }
+
+    /// The model of a living opponent, None if we know nothing about it yet
+    pub fn opponent_model(&self, player: PlayerId) -> Option<&OpponentModel> {
+        self.opponents.get(&player)
+    }
+
+    /// Models of all living opponents with observed moves
+    pub fn opponent_models(&self) -> impl Iterator<Item = (PlayerId, &OpponentModel)> {
+        self.opponents
+            .iter()
+            .map(|(player, model)| (*player, model))
+    }
+
+    /// Set what we know about the behaviour of an opponent before observing its moves, as
+    /// log-likelihood per behaviour in the order of [opponent::Behaviour::ALL].
+    pub fn set_behaviour_prior(&mut self, player: PlayerId, prior: [f32; 4]) {
+        self.opponents.entry(player).or_default().set_prior(prior);
+    }
+
+    pub fn player_name(&self, player: PlayerId) -> Option<&str> {
+        self.player_names.get(&player).map(|name| name.as_str())
+    }
+
+    /// Predicted distribution of the next move of a living player as (target, probability).
+    /// Without observed moves, all behaviours are weighted equally.
+    pub fn predict_moves(&self, player: PlayerId) -> Vec<(Position, f32)> {
+        match (self.player_heads.get(&player), self.opponents.get(&player)) {
+            (Some(head), Some(model)) => model.predict(self, player, head),
+            (Some(head), None) => OpponentModel::default().predict(self, player, head),
+            (None, _) => Vec::new(),
+        }
+    }
+
     fn is_occupied(&self, p: Position) -> bool {
-        self.field_occupation[p.as_dim()].is_some()
+        self.occupied.get(&p)
+    }
+
+    /// All fields which are not occupied by any player
+    fn free_fields(&self) -> Bitboard {
+        self.occupied.inverted()
+    }
+
+    /// The fields of all player heads, optionally except our own
+    fn head_fields(&self, include_mine: bool) -> Bitboard {
+        Bitboard::from_positions(
+            &self.game_size,
+            self.player_heads
+                .iter()
+                .filter(|(player, _head)| include_mine || **player != self.my_id)
+                .map(|(_player, head)| head),
+        )
+    }
+
+    /// Look at the board from the perspective of another living player, e.g. to score its room.
+    /// Returns our previous player to switch back to.
+    fn switch_player(&mut self, player: PlayerId) -> PlayerId {
+        if let Some(head) = self.player_heads.get(&player) {
+            self.my_position = head.clone();
+        }
+        std::mem::replace(&mut self.my_id, player)
+    }
+
+    /// Move the heads of the given players to the given fields simultaneously, without checking
+    /// for collisions. The returned [Undo] reverts the moves with [State::unmake_moves].
+    pub fn make_moves(&mut self, moves: &[(PlayerId, Position)]) -> Undo {
+        let mut undo = Undo {
+            my_position: self.my_position.clone(),
+            changes: Vec::with_capacity(moves.len()),
+        };
+        for (player, to) in moves {
+            undo.changes.push((
+                *player,
+                self.player_heads.insert(*player, to.clone()),
+                to.clone(),
+                self.field_occupation[to.as_dim()].replace(*player),
+            ));
+            self.occupied.set(to);
+            if *player == self.my_id {
+                self.my_position = to.clone();
+            }
+        }
+        undo
+    }
+
+    /// Remove the heads and trails of the given players like the server does when they die,
+    /// without forgetting what we know about them. Like moves, this is reverted with
+    /// [State::unmake_moves].
+    pub fn remove_players(&mut self, players: &[PlayerId]) -> Undo {
+        let mut undo = Undo {
+            my_position: self.my_position.clone(),
+            changes: Vec::new(),
+        };
+        for (index, field) in self.field_occupation.indexed_iter_mut() {
+            if let Some(player) = field.filter(|occupied_by| players.contains(occupied_by)) {
+                let position = Position {
+                    x: index.0 as u32,
+                    y: index.1 as u32,
+                };
+                self.occupied.unset(&position);
+                undo.changes.push((
+                    player,
+                    self.player_heads.remove(&player),
+                    position,
+                    field.take(),
+                ));
+            }
+        }
+        // Heads on a field taken by another player in a collision
+        for player in players {
+            if let Some(head) = self.player_heads.remove(player) {
+                let occupation = self.field_occupation[head.as_dim()];
+                undo.changes
+                    .push((*player, Some(head.clone()), head, occupation));
+            }
+        }
+        undo
+    }
+
+    /// Revert a [State::make_moves] or [State::remove_players]. Changes have to be unmade in
+    /// reverse order.
+    pub fn unmake_moves(&mut self, undo: Undo) {
+        for (player, previous_head, field, previous_occupation) in undo.changes.into_iter().rev() {
+            match previous_head {
+                Some(head) => self.player_heads.insert(player, head),
+                None => self.player_heads.remove(&player),
+            };
+            match previous_occupation {
+                Some(_player) => self.occupied.set(&field),
+                None => self.occupied.unset(&field),
+            }
+            self.field_occupation[field.as_dim()] = previous_occupation;
+        }
+        self.my_position = undo.my_position;
+    }
+}
+
+/// Everything needed to revert a [State::make_moves] or [State::remove_players]
+#[must_use]
+#[derive(Clone)]
+pub struct Undo {
+    my_position: Position,
+    /// Player, previous head, changed field and previous occupation of the field per change
+    changes: Vec<(PlayerId, Option<Position>, Position, Option<PlayerId>)>,
+}
+
+/// Score breakdown of a single candidate direction. Which components are set depends on the
+/// algorithm. Like in the algorithms' rankings, lower scores are better.
+#[derive(Clone, Debug)]
+pub struct CandidateScore {
+    pub direction: MoveDirection,
+    /// Evaluation of the empty space reachable after the move
+    pub space_score: Option<f32>,
+    /// Score of the direction itself. Depending on the algorithm, this is the overall score or the
+    /// tie-break for equal space scores.
+    pub direction_score: Option<f32>,
+    /// Contribution of nearby opponent heads
+    pub head_proximity: Option<f32>,
+    /// Whether an opponent head is next to the target field
+    pub head_adjacent: Option<bool>,
+    /// Probability that an opponent moves onto the target field as well
+    pub collision_risk: Option<f32>,
+    /// Contribution of walls next to the target field
+    pub wall_bonus: Option<f32>,
+    /// Random tie-break
+    pub random: Option<i32>,
+}
+
+impl CandidateScore {
+    pub fn new(direction: MoveDirection) -> Self {
+        CandidateScore {
+            direction,
+            space_score: None,
+            direction_score: None,
+            head_proximity: None,
+            head_adjacent: None,
+            collision_risk: None,
+            wall_bonus: None,
+            random: None,
+        }
+    }
+}
+
+impl fmt::Display for CandidateScore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}:", self.direction)?;
+        if let Some(score) = self.space_score {
+            write!(f, " space {:.3}", score)?;
+        }
+        if let Some(score) = self.direction_score {
+            write!(f, " direction {:.3}", score)?;
+        }
+        if let Some(score) = self.head_proximity {
+            write!(f, " heads {:.3}", score)?;
+        }
+        if let Some(adjacent) = self.head_adjacent {
+            write!(f, " adjacent head {}", adjacent)?;
+        }
+        if let Some(risk) = self.collision_risk {
+            write!(f, " collision {:.3}", risk)?;
+        }
+        if let Some(score) = self.wall_bonus {
+            write!(f, " wall {:.3}", score)?;
+        }
+        if let Some(random) = self.random {
+            write!(f, " random {}", random)?;
+        }
+        Ok(())
+    }
+}
+
+/// The move chosen by a strategy together with the scores of all candidate directions
+#[derive(Clone, Debug, Default)]
+pub struct Decision {
+    pub direction: Option<MoveDirection>,
+    /// All considered directions, best first
+    pub candidates: Vec<CandidateScore>,
+    /// The strategy ran out of time, so the decision is its best result so far or the fallback
+    /// move
+    pub timed_out: bool,
+}
+
+impl Decision {
+    /// Take the best candidate as chosen direction.
+    pub fn from_candidates(candidates: Vec<CandidateScore>) -> Self {
+        Decision {
+            direction: candidates.first().map(|c| c.direction.clone()),
+            candidates,
+            timed_out: false,
+        }
+    }
+
+    pub fn command(&self) -> Option<Command<'static>> {
+        self.direction.clone().map(Command::Move)
+    }
+}
+
+/// Point in time until which a decision has to be made. Strategies check it between expensive
+/// steps and give up early once it has expired.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    /// No time limit, e.g. for simulations and replays
+    pub fn none() -> Self {
+        Deadline(None)
+    }
+
+    pub fn after(budget: Duration) -> Self {
+        Deadline(Some(Instant::now() + budget))
+    }
+
+    pub fn expired(&self) -> bool {
+        self.0.is_some_and(|end| Instant::now() >= end)
+    }
+
+    /// Deadline after the given share of the time remaining until this one, leaving the rest as a
+    /// safety margin
+    pub fn share(&self, share: f64) -> Self {
+        Deadline(self.0.map(|end| {
+            let now = Instant::now();
+            now + end.saturating_duration_since(now).mul_f64(share)
+        }))
+    }
+
+    /// The earlier of both deadlines
+    pub fn earlier(&self, other: &Deadline) -> Self {
+        match (self.0, other.0) {
+            (Some(a), Some(b)) => Deadline(Some(a.min(b))),
+            (a, b) => Deadline(a.or(b)),
+        }
+    }
+}
+
+/// Decision times over the ticks of a game
+#[derive(Default, Debug)]
+pub struct TimingStats {
+    pub ticks: usize,
+    /// Ticks in which the strategy ran out of time
+    pub timeouts: usize,
+    pub slowest: Duration,
+}
+
+impl TimingStats {
+    pub fn record(&mut self, duration: Duration, decision: &Decision) {
+        self.ticks += 1;
+        if decision.timed_out {
+            self.timeouts += 1;
+        }
+        self.slowest = self.slowest.max(duration);
+    }
+}
+
+impl fmt::Display for TimingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} ticks ran out of time, slowest decision took {} ms",
+            self.timeouts,
+            self.ticks,
+            self.slowest.as_millis()
+        )
+    }
+}
+
+/// A bot algorithm. One instance is used for all games of a connection, so implementations may
+/// keep information across ticks and games.
+pub trait Strategy {
+    /// Called when a new game starts, after the state has been reset for it.
+    fn init(&mut self, _state: &State) {}
+
+    /// Decide on the move in the current tick. Long running loops should stop once the deadline
+    /// expired and return the best result so far. Without a direction, a fallback move is used.
+    fn decide(&mut self, state: &mut State, rng: &mut StdRng, deadline: &Deadline) -> Decision;
+
+    /// Called when our own player died.
+    fn on_death(&mut self, _state: &State) {}
+
+    /// Called when the game is over.
+    fn on_game_end(&mut self, _state: &State, _won: bool) {}
+}
+
+type StrategyConstructor = fn(&AlgorithmConfig) -> Result<Box<dyn Strategy>, UnknownStrategy>;
+
+const STRATEGIES: &[(&str, StrategyConstructor)] = &[
+    ("flood", |config| {
+        Ok(Box::new(algorithm1::Flood::new(config.flood.clone())))
+    }),
+    ("taint", |config| {
+        Ok(Box::new(algorithm2::Taint::new(config.taint.clone())))
+    }),
+    ("hybrid", |config| {
+        Ok(Box::new(algorithm3::Hybrid::new(config.hybrid.clone())))
+    }),
+    ("ranked", |config| {
+        Ok(Box::new(algorithm4::Ranked::new(config.ranked.clone())))
+    }),
+    ("search", |config| {
+        Ok(Box::new(algorithm5::Search::new(
+            config.search.clone(),
+            config.ranked.clone(),
+        )))
+    }),
+    ("voronoi", |_config| Ok(Box::new(algorithm6::Territory))),
+    // Plays like ranked until sealed off, then fills the region, regardless of `endgame`
+    ("chamber", |config| {
+        Ok(Box::new(endgame::Survival::new(Box::new(
+            algorithm4::Ranked::new(config.ranked.clone()),
+        ))))
+    }),
+    ("mcts", |config| {
+        Ok(Box::new(algorithm8::Mcts::new(config)?))
+    }),
+];
+
+pub fn available_strategies() -> impl Iterator<Item = &'static str> {
+    STRATEGIES.iter().map(|(name, _constructor)| *name)
+}
+
+/// A strategy name in the config which does not name a usable strategy
+#[derive(Debug)]
+pub enum UnknownStrategy {
+    /// The `algorithm` setting
+    Algorithm(String),
+    /// The `rollout_policy` of the mcts strategy
+    RolloutPolicy(String),
+}
+
+impl fmt::Display for UnknownStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnknownStrategy::Algorithm(name) => write!(
+                f,
+                "Unknown algorithm \"{}\", available strategies: {}",
+                name,
+                available_strategies().collect::<Vec<_>>().join(", ")
+            ),
+            UnknownStrategy::RolloutPolicy(name) => write!(
+                f,
+                "Invalid rollout policy \"{}\", use random, model or one of the strategies {}",
+                name,
+                available_strategies()
+                    .filter(|strategy| *strategy != "mcts")
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
     }
+}
+
+impl std::error::Error for UnknownStrategy {}
 
-    fn simulate_step(&self, step_to: &Position) -> State {
-        let mut new_state = self.clone();
-        new_state.field_occupation[step_to.as_dim()].replace(self.my_id);
-        new_state
-            .player_heads
-            .insert(new_state.my_id, step_to.clone());
-        new_state.my_position = step_to.clone();
-        new_state
+/// Create the strategy selected in the config, switching to the survival mode when sealed off if
+/// enabled.
+pub fn create_strategy(config: &AlgorithmConfig) -> Result<Box<dyn Strategy>, UnknownStrategy> {
+    let constructor = STRATEGIES
+        .iter()
+        .find(|(name, _constructor)| *name == config.algorithm)
+        .map(|(_name, constructor)| constructor)
+        .ok_or_else(|| UnknownStrategy::Algorithm(config.algorithm.clone()))?;
+    let strategy = constructor(config)?;
+    // The chamber strategy has the survival mode built in
+    if config.endgame && config.algorithm != "chamber" {
+        Ok(Box::new(endgame::Survival::new(strategy)))
+    } else {
+        Ok(strategy)
     }
 }
 
+/// Let the strategy decide on the move in the current tick. If the strategy does not finish before
+/// the deadline, its best result so far is used. If it has no direction at all, a cheap fallback
+/// move is returned instead.
 pub fn decide_action(
     state: &mut State,
-    rng: &mut ThreadRng,
-    config: &AlgorithmConfig,
-) -> Option<Command<'static>> {
+    rng: &mut StdRng,
+    strategy: &mut dyn Strategy,
+    deadline: &Deadline,
+) -> Decision {
     if state.game_size.x == 0 || state.game_size.y == 0 {
-        return None;
+        return Decision::default();
+    }
+
+    let mut decision = strategy.decide(state, rng, deadline);
+    decision.timed_out |= deadline.expired();
+    if decision.direction.is_none() {
+        let fallback = fallback_decision(state);
+        if fallback.direction.is_some() {
+            warn!(
+                "No move from the strategy, using fallback move {:?}",
+                fallback.direction
+            );
+            return Decision {
+                timed_out: decision.timed_out,
+                ..fallback
+            };
+        }
     }
+    decision
+}
+
+/// A safe move which is quick to compute: Avoid fields next to opponent heads and prefer the
+/// field with the most free neighbours.
+fn fallback_decision(state: &State) -> Decision {
+    let mut candidates: Vec<_> = iter_directions()
+        .map(|d| {
+            (
+                d,
+                move_by_direction(&state.my_position, d, &state.game_size),
+            )
+        })
+        .filter(|(_d, p)| !state.is_occupied(p.clone()))
+        .map(|(d, p)| {
+            let free_neighbours = iter_directions()
+                .filter(|d| !state.is_occupied(move_by_direction(&p, d, &state.game_size)))
+                .count();
+            CandidateScore {
+                space_score: Some(-(free_neighbours as f32)),
+                head_adjacent: Some(has_neighbour_head(&p, state)),
+                ..CandidateScore::new(d.clone())
+            }
+        })
+        .collect();
+    candidates.sort_by_key(|c| (c.head_adjacent, c.space_score.map(OrderedFloat)));
+    Decision::from_candidates(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scenario::{parse, player};
+    use super::*;
+    use rand::SeedableRng;
+
+    /// Everything [State::make_moves] changes
+    fn board_of(
+        state: &State,
+    ) -> (
+        ndarray::Array2<Option<PlayerId>>,
+        Bitboard,
+        BTreeMap<PlayerId, Position>,
+        Position,
+    ) {
+        (
+            state.field_occupation.clone(),
+            state.occupied.clone(),
+            state.player_heads.clone(),
+            state.my_position.clone(),
+        )
+    }
+
+    fn pos(x: u32, y: u32) -> Position {
+        Position { x, y }
+    }
+
+    /// Decides on a fixed direction, like a strategy stopped by the deadline
+    struct Fixed(Option<MoveDirection>);
+
+    impl Strategy for Fixed {
+        fn decide(
+            &mut self,
+            _state: &mut State,
+            _rng: &mut StdRng,
+            _deadline: &Deadline,
+        ) -> Decision {
+            Decision {
+                direction: self.0.clone(),
+                ..Decision::default()
+            }
+        }
+    }
+
+    #[test]
+    fn decide_action_keeps_the_decision_at_the_deadline() {
+        let mut state = parse(
+            "
+            aaaa
+            a@.A
+            aaaa
+            ",
+        );
+        let decision = decide_action(
+            &mut state,
+            &mut StdRng::seed_from_u64(0),
+            &mut Fixed(Some(MoveDirection::Up)),
+            &Deadline::after(Duration::ZERO),
+        );
+        assert_eq!(decision.direction, Some(MoveDirection::Up));
+        assert!(decision.timed_out);
+    }
+
+    #[test]
+    fn decide_action_falls_back_without_direction() {
+        let mut state = parse(
+            "
+            aaaa
+            a@.A
+            aaaa
+            ",
+        );
+        let decision = decide_action(
+            &mut state,
+            &mut StdRng::seed_from_u64(0),
+            &mut Fixed(None),
+            &Deadline::none(),
+        );
+        assert_eq!(decision.direction, Some(MoveDirection::Right));
+        assert!(!decision.timed_out);
+    }
+
+    #[test]
+    fn unmake_moves_restores_state() {
+        let mut state = parse(
+            "
+            ..a..
+            *@A..
+            .bB..
+            ..cC.
+            ",
+        );
+        state.update_from_answer(&Answer::Die(vec![player('c')]));
+        let before = board_of(&state);
+
+        // We and A collide on the same field, B moves onto its own trail and the dead C moves
+        // onto the field where its head was.
+        let first = state.make_moves(&[
+            (state.my_id, pos(1, 0)),
+            (player('a'), pos(1, 0)),
+            (player('b'), pos(1, 2)),
+            (player('c'), pos(3, 3)),
+        ]);
+        assert_eq!(state.my_position, pos(1, 0));
+        assert_eq!(
+            state.field_occupation[pos(1, 0).as_dim()],
+            Some(player('a'))
+        );
+        assert_eq!(state.player_head(player('c')), Some(&pos(3, 3)));
+        assert!(state.occupied.get(&pos(3, 3)));
+        let after_first = board_of(&state);
+
+        let second = state.make_moves(&[(state.my_id, pos(0, 0)), (player('b'), pos(3, 2))]);
+        assert_eq!(state.my_position, pos(0, 0));
+        state.unmake_moves(second);
+        assert_eq!(board_of(&state), after_first);
+
+        state.unmake_moves(first);
+        assert_eq!(board_of(&state), before);
+        assert_eq!(state.player_head(player('c')), None);
+        assert!(!state.occupied.get(&pos(1, 0)));
+        assert!(state.occupied.get(&pos(1, 2)));
+    }
+
+    #[test]
+    fn unmake_moves_restores_removed_players() {
+        let mut state = parse(
+            "
+            ..a..
+            *@A.B
+            ....b
+            ",
+        );
+        let before = board_of(&state);
+
+        // A and B collide, so A's head is on a field taken by B
+        let moves = state.make_moves(&[(player('a'), pos(3, 1)), (player('b'), pos(3, 1))]);
+        let removed = state.remove_players(&[player('a'), player('b')]);
+        assert_eq!(state.player_head(player('a')), None);
+        assert_eq!(state.player_head(player('b')), None);
+        assert_eq!(state.field_occupation.iter().flatten().count(), 2);
+        assert_eq!(state.occupied.count(), 2);
 
-    match config.algorithm {
-        0 => algorithm1::decide_action(state, rng, config),
-        1 => algorithm2::decide_action(state, rng, config),
-        2 => algorithm3::decide_action(state, rng, config),
-        3 => algorithm4::decide_action(state, rng, config),
-        _ => panic!("Unknown algorithm variant {}", config.algorithm),
+        state.unmake_moves(removed);
+        state.unmake_moves(moves);
+        assert_eq!(board_of(&state), before);
     }
 }
@@ -1,15 +1,48 @@
+use super::bitboard::Bitboard;
 use super::helper::{has_wall, move_by_direction, point_to_point_distance};
-use super::State;
-use crate::{AlgorithmConfig, Command, MoveDirection, Position};
+use super::{CandidateScore, Deadline, Decision, State, Strategy};
+use crate::{MoveDirection, Position};
 use log::debug;
 use ordered_float::OrderedFloat;
-use rand::rngs::ThreadRng;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
-pub fn decide_action(
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct FloodConfig {
+    /// Subtracted from the direction score if the target field is next to a wall
+    pub wall_bonus: f32,
+}
+
+impl Default for FloodConfig {
+    fn default() -> Self {
+        FloodConfig { wall_bonus: 0.03 }
+    }
+}
+
+/// Chooses the direction with the largest reachable empty space per opponent head.
+pub struct Flood {
+    config: FloodConfig,
+}
+
+impl Flood {
+    pub fn new(config: FloodConfig) -> Self {
+        Flood { config }
+    }
+}
+
+impl Strategy for Flood {
+    fn decide(&mut self, state: &mut State, rng: &mut StdRng, deadline: &Deadline) -> Decision {
+        decide_action(state, rng, deadline, &self.config)
+    }
+}
+
+fn decide_action(
     state: &mut State,
-    _rng: &mut ThreadRng,
-    _config: &AlgorithmConfig,
-) -> Option<Command<'static>> {
+    _rng: &mut StdRng,
+    deadline: &Deadline,
+    config: &FloodConfig,
+) -> Decision {
     let mut directions = [
         MoveDirection::Up,
         MoveDirection::Down,
@@ -17,79 +50,49 @@ pub fn decide_action(
         MoveDirection::Right,
     ]
     .iter()
-    .filter(|d| !state.is_occupied(move_by_direction(&state.my_position, &d, &state.game_size)))
+    .filter(|d| !state.is_occupied(move_by_direction(&state.my_position, d, &state.game_size)))
+    .take_while(|_d| !deadline.expired())
     .map(|d| {
+        let space_score = evaluate_empty_space(&explore_empty_space(
+            state,
+            move_by_direction(&state.my_position, d, &state.game_size),
+            deadline,
+        ));
+        let (head_proximity, wall_bonus) = evaluate_direction(d, state, config);
+        let direction_score = head_proximity - wall_bonus;
         (
-            explore_empty_space(
-                &state,
-                move_by_direction(&state.my_position, &d, &state.game_size),
-            ),
-            d,
+            (OrderedFloat(space_score), OrderedFloat(direction_score)),
+            CandidateScore {
+                space_score: Some(space_score),
+                direction_score: Some(direction_score),
+                head_proximity: Some(head_proximity),
+                wall_bonus: Some(wall_bonus),
+                ..CandidateScore::new(d.clone())
+            },
         )
     })
     .collect::<Vec<_>>();
-    directions.sort_by_key(|(r, d)| {
-        (
-            OrderedFloat(evaluate_empty_space(&r)),
-            OrderedFloat(evaluate_direction(&d, state)),
-        )
-    });
+    directions.sort_by_key(|(key, _candidate)| *key);
     debug!("Directions: {:?}", directions);
-    if directions.is_empty() {
-        None
-    } else {
-        Some(Command::Move(directions[0].1.clone()))
-    }
+    Decision::from_candidates(directions.into_iter().map(|(_key, c)| c).collect())
 }
 
 #[derive(Debug, Default)]
 struct EmptySpaceState {
     size: usize,
     num_snake_heads: usize,
-    sum_x: u32,
-    sum_y: u32,
-}
-
-impl EmptySpaceState {
-    fn mass_center(&self) -> (f32, f32) {
-        (
-            (self.sum_x as f32) / (self.size as f32),
-            (self.sum_y as f32) / (self.size as f32),
-        )
-    }
 }
 
-fn explore_empty_space(state: &State, position: Position) -> EmptySpaceState {
-    let mut result = EmptySpaceState::default();
-    let mut visited = std::collections::HashSet::new();
-    let mut queue = std::collections::VecDeque::new();
-
-    visited.insert(position.clone());
-    queue.push_back(position.clone());
-
-    while let Some(p) = queue.pop_front() {
-        if state.player_heads.values().any(|head| *head == p) {
-            result.num_snake_heads += 1;
-        }
-        if !state.is_occupied(p.clone()) {
-            result.size += 1;
-            result.sum_x += p.x;
-            result.sum_y += p.y;
-            for direction in [
-                MoveDirection::Up,
-                MoveDirection::Down,
-                MoveDirection::Left,
-                MoveDirection::Right,
-            ] {
-                let next_pos = move_by_direction(&p, &direction, &state.game_size);
-                if !visited.contains(&next_pos) {
-                    visited.insert(next_pos.clone());
-                    queue.push_back(next_pos);
-                }
-            }
-        }
+fn explore_empty_space(state: &State, position: Position, deadline: &Deadline) -> EmptySpaceState {
+    let start = Bitboard::from_positions(&state.game_size, [&position]);
+    let region = state.free_fields().flood_fill_until(&start, deadline);
+    let mut visited = region.neighbours();
+    visited |= &region;
+    visited |= &start;
+    EmptySpaceState {
+        size: region.count(),
+        num_snake_heads: state.head_fields(true).count_common(&visited),
     }
-    return result;
 }
 
 fn evaluate_empty_space(state: &EmptySpaceState) -> f32 {
@@ -100,7 +103,9 @@ fn evaluate_empty_space(state: &EmptySpaceState) -> f32 {
     }
 }
 
-fn evaluate_direction(d: &MoveDirection, state: &State) -> f32 {
+/// Returns the proximity to the next opponent head and the bonus for a wall next to the target
+/// field. The direction score is the proximity minus the wall bonus.
+fn evaluate_direction(d: &MoveDirection, state: &State, config: &FloodConfig) -> (f32, f32) {
     let next_position = move_by_direction(&state.my_position, d, &state.game_size);
 
     let min_player_distance: f32 = state
@@ -110,7 +115,7 @@ fn evaluate_direction(d: &MoveDirection, state: &State) -> f32 {
         .map(|(_p, pos)| {
             OrderedFloat(point_to_point_distance(
                 &next_position,
-                &pos,
+                pos,
                 &state.game_size,
             ))
         })
@@ -118,10 +123,12 @@ fn evaluate_direction(d: &MoveDirection, state: &State) -> f32 {
         .unwrap()
         .0;
 
-    1.0 / min_player_distance
-        - if has_wall(&next_position, state) {
-            0.03
+    (
+        1.0 / min_player_distance,
+        if has_wall(&next_position, state) {
+            config.wall_bonus
         } else {
             0.0
-        }
+        },
+    )
 }
@@ -1,16 +1,75 @@
-use super::helper::{has_neighbour_head, has_wall, move_by_direction};
-use super::State;
-use crate::{AlgorithmConfig, Command, MoveDirection, Position};
+use super::bitboard::Bitboard;
+use super::helper::{contested_fields, has_wall, move_by_direction};
+use super::{CandidateScore, Deadline, Decision, State, Strategy};
+use crate::{MoveDirection, Position};
 use log::debug;
 use ordered_float::OrderedFloat;
-use rand::rngs::ThreadRng;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
-pub fn decide_action(
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct TaintConfig {
+    /// Weight of the fields next to the target field
+    pub max_field_distance_scaling: f32,
+    /// Weight of the fields far away from the target field
+    pub min_field_distance_scaling: f32,
+    /// Factor of the score if the target field is next to a wall
+    pub factor_wall: f32,
+    /// Factor of the score if an opponent surely moves onto the target field, applied in
+    /// proportion to the probability of that
+    pub factor_head: f32,
+    /// How fast the weight of the fields decreases with their distance
+    pub distance_exponent: f32,
+    /// Value of a field far away from all opponent heads
+    pub max_field_score: f32,
+    /// Base of the taint of a field, the lower, the stronger opponent heads taint their
+    /// surroundings
+    pub min_field_score: f32,
+    /// How fast the taint decreases with the distance to an opponent head
+    pub field_score_alpha: f32,
+}
+
+impl Default for TaintConfig {
+    fn default() -> Self {
+        TaintConfig {
+            max_field_distance_scaling: 1.0,
+            min_field_distance_scaling: 0.9,
+            factor_wall: 1.05,
+            factor_head: 0.85,
+            distance_exponent: 0.6,
+            max_field_score: 1.0,
+            min_field_score: 0.4,
+            field_score_alpha: 0.6,
+        }
+    }
+}
+
+/// Chooses the direction with the most reachable fields, weighted by their distance to opponent heads.
+pub struct Taint {
+    config: TaintConfig,
+}
+
+impl Taint {
+    pub fn new(config: TaintConfig) -> Self {
+        Taint { config }
+    }
+}
+
+impl Strategy for Taint {
+    fn decide(&mut self, state: &mut State, rng: &mut StdRng, deadline: &Deadline) -> Decision {
+        decide_action(state, rng, deadline, &self.config)
+    }
+}
+
+fn decide_action(
     state: &mut State,
-    _rng: &mut ThreadRng,
-    _config: &AlgorithmConfig,
-) -> Option<Command<'static>> {
-    let tainted_fields = taint_fields_near_heads(state);
+    _rng: &mut StdRng,
+    deadline: &Deadline,
+    config: &TaintConfig,
+) -> Decision {
+    let tainted_fields = taint_fields_near_heads(state, config, deadline);
+    let contested = contested_fields(state);
     let mut directions = [
         MoveDirection::Up,
         MoveDirection::Down,
@@ -18,50 +77,55 @@ pub fn decide_action(
         MoveDirection::Right,
     ]
     .iter()
-    .filter(|d| !state.is_occupied(move_by_direction(&state.my_position, &d, &state.game_size)))
+    .filter(|d| !state.is_occupied(move_by_direction(&state.my_position, d, &state.game_size)))
+    .take_while(|_d| !deadline.expired())
     .map(|d| {
-        (
-            evaluate_direction_weighted(
-                &state,
-                &move_by_direction(&state.my_position, &d, &state.game_size),
-                &tainted_fields,
-            ),
-            d,
-        )
+        let position = move_by_direction(&state.my_position, d, &state.game_size);
+        let space_score =
+            evaluate_direction_weighted(state, &position, &tainted_fields, deadline, config);
+        let wall = has_wall(&position, state);
+        let collision_risk = contested.probability(&position);
+        let factor = if wall {
+            config.factor_wall
+        } else {
+            1.0 + (config.factor_head - 1.0) * collision_risk
+        };
+        CandidateScore {
+            space_score: Some(space_score),
+            direction_score: Some(space_score * factor),
+            collision_risk: Some(collision_risk),
+            wall_bonus: Some(if wall { config.factor_wall } else { 1.0 }),
+            ..CandidateScore::new(d.clone())
+        }
     })
     .collect::<Vec<_>>();
-    directions.sort_by_key(|(r, _d)| OrderedFloat(*r));
+    directions.sort_by_key(|c| OrderedFloat(c.direction_score.unwrap()));
     debug!("Directions: {:?}", directions);
-    if directions.is_empty() {
-        None
-    } else {
-        Some(Command::Move(directions[0].1.clone()))
-    }
+    Decision::from_candidates(directions)
 }
 
-const MAX_FIELD_DISTANCE_SCALING: f32 = 1.0;
-const MIN_FIELD_DISTANCE_SCALING: f32 = 0.9;
-const FACTOR_WALL: f32 = 1.05;
-const FACTOR_HEAD: f32 = 0.85;
-const DISTANCE_EXPONENT: f32 = 0.6;
-
 fn evaluate_direction_weighted(
     state: &State,
     position: &Position,
     tainted_fields: &ndarray::Array2<f32>,
+    deadline: &Deadline,
+    config: &TaintConfig,
 ) -> f32 {
     let mut result = 0.0;
-    let mut visited = std::collections::HashSet::new();
+    let mut visited = Bitboard::new(&state.game_size);
     let mut queue = std::collections::VecDeque::new();
 
-    visited.insert(position.clone());
+    visited.set(position);
     queue.push_back((0usize, position.clone()));
 
     while let Some((dist, p)) = queue.pop_front() {
+        if deadline.expired() {
+            break;
+        }
         if !state.is_occupied(p.clone()) {
-            let scaling = (1.0 / ((dist + 1) as f32).powf(DISTANCE_EXPONENT))
-                * (MAX_FIELD_DISTANCE_SCALING - MIN_FIELD_DISTANCE_SCALING)
-                + MIN_FIELD_DISTANCE_SCALING;
+            let scaling = (1.0 / ((dist + 1) as f32).powf(config.distance_exponent))
+                * (config.max_field_distance_scaling - config.min_field_distance_scaling)
+                + config.min_field_distance_scaling;
             result += tainted_fields[p.as_dim()] * scaling;
             for direction in [
                 MoveDirection::Up,
@@ -70,44 +134,43 @@ fn evaluate_direction_weighted(
                 MoveDirection::Right,
             ] {
                 let next_pos = move_by_direction(&p, &direction, &state.game_size);
-                if !visited.contains(&next_pos) {
-                    visited.insert(next_pos.clone());
+                if !visited.get(&next_pos) {
+                    visited.set(&next_pos);
                     queue.push_back((dist + 1, next_pos));
                 }
             }
         }
     }
-    return -result
-        * if has_wall(position, state) {
-            FACTOR_WALL
-        } else if has_neighbour_head(position, state) {
-            FACTOR_HEAD
-        } else {
-            1.0
-        };
+    -result
 }
 
-const MAX_FIELD_SCORE: f32 = 1.0;
-const MIN_FIELD_SCORE: f32 = 0.4;
-const FIELD_SCORE_ALPHA: f32 = 0.6;
-
-// IDEA: field score = 1.0 * (1 - (MIN_FIELD_SCORE ^ (alpha * distance_1))) * (1 - (MIN_FIELD_SCORE ^ (alpha * distance_2))) ...
-fn taint_fields_near_heads(state: &State) -> ndarray::Array2<f32> {
-    let mut result = ndarray::Array2::from_elem(state.game_size.as_dim(), MAX_FIELD_SCORE);
+// IDEA: field score = 1.0 * (1 - (min_field_score ^ (alpha * distance_1))) * (1 - (min_field_score ^ (alpha * distance_2))) ...
+pub(crate) fn taint_fields_near_heads(
+    state: &State,
+    config: &TaintConfig,
+    deadline: &Deadline,
+) -> ndarray::Array2<f32> {
+    let mut result = ndarray::Array2::from_elem(state.game_size.as_dim(), config.max_field_score);
 
     for (player, head) in state.player_heads.iter() {
         if *player == state.my_id {
             continue;
         }
 
-        let mut visited = std::collections::HashSet::new();
+        let mut visited = Bitboard::new(&state.game_size);
         let mut queue = std::collections::VecDeque::new();
 
-        visited.insert(head.clone());
+        visited.set(head);
         queue.push_back((-1i32, head.clone()));
 
         while let Some((dist, p)) = queue.pop_front() {
-            result[p.as_dim()] *= 1.0 - MIN_FIELD_SCORE.powf(dist as f32 * FIELD_SCORE_ALPHA);
+            if deadline.expired() {
+                return result;
+            }
+            result[p.as_dim()] *= 1.0
+                - config
+                    .min_field_score
+                    .powf(dist as f32 * config.field_score_alpha);
             for direction in [
                 MoveDirection::Up,
                 MoveDirection::Down,
@@ -115,13 +178,13 @@ fn taint_fields_near_heads(state: &State) -> ndarray::Array2<f32> {
                 MoveDirection::Right,
             ] {
                 let next_pos = move_by_direction(&p, &direction, &state.game_size);
-                if !visited.contains(&next_pos) && !state.is_occupied(next_pos.clone()) {
-                    visited.insert(next_pos.clone());
+                if !visited.get(&next_pos) && !state.is_occupied(next_pos.clone()) {
+                    visited.set(&next_pos);
                     queue.push_back((dist + 1, next_pos));
                 }
             }
         }
     }
 
-    return result;
+    result
 }
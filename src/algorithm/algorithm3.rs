@@ -1,55 +1,127 @@
 use std::collections::HashSet;
 
+use super::bitboard::Bitboard;
 use super::helper::{
-    distance_to_next_opponent_head, has_wall, move_by_direction,
+    contested_fields, distance_to_next_opponent_head, has_wall, move_by_direction, ContestedFields,
 };
-use super::State;
-use crate::algorithm::helper::has_neighbour_head;
+use super::{CandidateScore, Deadline, Decision, State, Strategy};
 use crate::client::PlayerId;
-use crate::{AlgorithmConfig, Command, MoveDirection, Position};
+use crate::{MoveDirection, Position};
 use log::{debug, info};
 use ordered_float::OrderedFloat;
-use rand::rngs::ThreadRng;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
-pub fn decide_action(
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct HybridConfig {
+    /// Exponent of the number of heads next to an empty space, dividing its size
+    pub heads_exponent: f32,
+    /// Exponent of the number of players whose trails bound an empty space, multiplying its size
+    pub walls_exponent: f32,
+    /// Play compact if our space score is better than this share of the best opponent's
+    pub compact_mode_threshold: f32,
+    /// Weight of the space lost by the step in compact mode
+    pub compact_space_weight: f32,
+    /// Subtracted from the score in compact mode if the target field is next to a wall
+    pub compact_wall_bonus: f32,
+    /// Exponent of the change of our space score by the step outside of compact mode
+    pub space_change_exponent: f32,
+    /// Factor of the score outside of compact mode if an opponent surely moves onto the target
+    /// field, applied in proportion to the probability of that
+    pub factor_head: f32,
+    /// Value of a field far away from all opponent heads
+    pub max_field_score: f32,
+    /// Base of the taint of a field, the lower, the stronger opponent heads taint their
+    /// surroundings
+    pub min_field_score: f32,
+    /// How fast the taint decreases with the distance to an opponent head
+    pub field_score_alpha: f32,
+}
+
+impl Default for HybridConfig {
+    fn default() -> Self {
+        HybridConfig {
+            heads_exponent: 0.3,
+            walls_exponent: 0.3,
+            compact_mode_threshold: 0.8,
+            compact_space_weight: 0.2,
+            compact_wall_bonus: 0.03,
+            space_change_exponent: 0.5,
+            factor_head: 0.85,
+            max_field_score: 0.85,
+            min_field_score: 0.8,
+            field_score_alpha: 0.9,
+        }
+    }
+}
+
+/// Combines the space evaluation of [super::algorithm1] with the taint map of [super::algorithm2].
+pub struct Hybrid {
+    config: HybridConfig,
+}
+
+impl Hybrid {
+    pub fn new(config: HybridConfig) -> Self {
+        Hybrid { config }
+    }
+}
+
+impl Strategy for Hybrid {
+    fn decide(&mut self, state: &mut State, rng: &mut StdRng, deadline: &Deadline) -> Decision {
+        decide_action(state, rng, deadline, &self.config)
+    }
+}
+
+fn decide_action(
     state: &mut State,
-    rng: &mut ThreadRng,
-    _config: &AlgorithmConfig,
-) -> Option<Command<'static>> {
-    let opponent_rooms = evaluate_opponents_rooms(state);
-    let tainted_fields = taint_fields_near_heads(state);
-    debug!("Opponent rooms: {:?}", opponent_rooms);
-
-    let mut directions = [
+    _rng: &mut StdRng,
+    deadline: &Deadline,
+    config: &HybridConfig,
+) -> Decision {
+    let opponents = Opponents {
+        rooms: evaluate_opponents_rooms(state, deadline, config),
+        taint: taint_fields_near_heads(state, deadline, config),
+        contested: contested_fields(state),
+    };
+    debug!("Opponent rooms: {:?}", opponents.rooms);
+
+    let possible_directions = [
         MoveDirection::Up,
         MoveDirection::Down,
         MoveDirection::Left,
         MoveDirection::Right,
     ]
     .iter()
-    .filter(|d| !state.is_occupied(move_by_direction(&state.my_position, &d, &state.game_size)))
-    .map(|d| {
-        (
-            explore_empty_space(
-                &state,
-                move_by_direction(&state.my_position, &d, &state.game_size),
-            ),
-            d,
-        )
-    })
+    .filter(|d| !state.is_occupied(move_by_direction(&state.my_position, d, &state.game_size)))
     .collect::<Vec<_>>();
-    directions.sort_by_cached_key(|(r, d)| {
+    let mut directions = possible_directions
+        .into_iter()
+        .take_while(|_d| !deadline.expired())
+        .map(|d| {
+            let r = explore_empty_space(
+                state,
+                move_by_direction(&state.my_position, d, &state.game_size),
+                deadline,
+            );
+            debug!("Empty space {:?}: {:?}", d, r);
+            CandidateScore {
+                space_score: Some(evaluate_empty_space(&r, config)),
+                direction_score: Some(evaluate_direction(
+                    d, &r, state, &opponents, deadline, config,
+                )),
+                ..CandidateScore::new(d.clone())
+            }
+        })
+        .collect::<Vec<_>>();
+    directions.sort_by_key(|c| {
         (
-            OrderedFloat(evaluate_empty_space(&r)),
-            OrderedFloat(evaluate_direction(&d, &r, state, rng, &opponent_rooms, &tainted_fields)),
+            OrderedFloat(c.space_score.unwrap()),
+            OrderedFloat(c.direction_score.unwrap()),
         )
     });
     debug!("Directions: {:?}", directions);
-    if directions.is_empty() {
-        None
-    } else {
-        Some(Command::Move(directions[0].1.clone()))
-    }
+    Decision::from_candidates(directions)
 }
 
 #[derive(Debug, Default)]
@@ -59,113 +131,122 @@ struct EmptySpaceState {
     wall_players: HashSet<PlayerId>,
 }
 
-fn explore_empty_space(state: &State, position: Position) -> EmptySpaceState {
-    let mut result = EmptySpaceState::default();
-    let mut visited = std::collections::HashSet::new();
-    let mut queue = std::collections::VecDeque::new();
-
-    visited.insert(position.clone());
-    queue.push_back(position.clone());
+/// Flood fill from `position` through empty fields. The start field is expanded even if it is
+/// occupied, e.g. by the head of a player.
+fn explore_empty_space(state: &State, position: Position, deadline: &Deadline) -> EmptySpaceState {
+    let start = Bitboard::from_positions(&state.game_size, [&position]);
+    let free = state.free_fields();
+    let mut expandable = free.clone();
+    expandable |= &start;
+    let region = expandable.flood_fill_until(&start, deadline);
+    let mut visited = region.neighbours();
+    visited |= &region;
 
-    while let Some(p) = queue.pop_front() {
-        if state.player_heads.values().any(|head| *head == p) {
-            result.num_snake_heads += 1;
-        }
-        if !state.is_occupied(p.clone()) {
-            result.size += 1;
-        }
-        if !state.is_occupied(p.clone()) || p == position {
-            for direction in [
-                MoveDirection::Up,
-                MoveDirection::Down,
-                MoveDirection::Left,
-                MoveDirection::Right,
-            ] {
-                let next_pos = move_by_direction(&p, &direction, &state.game_size);
-                if !visited.contains(&next_pos) {
-                    visited.insert(next_pos.clone());
-                    queue.push_back(next_pos);
-                }
-            }
-        } else {
-            result.wall_players.insert(state.field_occupation[p.as_dim()].unwrap());
-        }
+    let mut walls = visited.clone();
+    walls.remove(&free);
+    walls.remove(&start);
+    EmptySpaceState {
+        size: visited.count_common(&free),
+        num_snake_heads: state.head_fields(true).count_common(&visited),
+        wall_players: walls
+            .iter()
+            .map(|p| state.field_occupation[p.as_dim()].unwrap())
+            .collect(),
     }
-    return result;
 }
 
-fn evaluate_empty_space(state: &EmptySpaceState) -> f32 {
+fn evaluate_empty_space(state: &EmptySpaceState, config: &HybridConfig) -> f32 {
     if state.num_snake_heads == 0 {
         0f32
     } else {
-        -(state.size as f32) / (state.num_snake_heads as f32).powf(0.3) * (state.wall_players.len() as f32).powf(0.3)
+        -(state.size as f32) / (state.num_snake_heads as f32).powf(config.heads_exponent)
+            * (state.wall_players.len() as f32).powf(config.walls_exponent)
     }
 }
 
-fn evaluate_opponents_rooms(state: &State) -> Vec<f32> {
+fn evaluate_opponents_rooms(state: &State, deadline: &Deadline, config: &HybridConfig) -> Vec<f32> {
     state
         .player_heads
         .iter()
         .filter(|(player, _head)| **player != state.my_id)
-        .map(|(_player, head)| explore_empty_space(state, head.clone()))
-        .map(|space| evaluate_empty_space(&space))
+        .take_while(|_| !deadline.expired())
+        .map(|(_player, head)| explore_empty_space(state, head.clone(), deadline))
+        .map(|space| evaluate_empty_space(&space, config))
         .collect()
 }
 
 type FieldTaint = ndarray::Array2<f32>;
 
+/// What we know about the opponents in this tick, shared by the evaluation of all directions
+struct Opponents {
+    /// Score of the empty space of every opponent
+    rooms: Vec<f32>,
+    taint: FieldTaint,
+    contested: ContestedFields,
+}
+
 fn evaluate_direction(
     d: &MoveDirection,
     empty_space: &EmptySpaceState,
-    state: &State,
-    _rng: &mut ThreadRng,
-    opponent_rooms: &Vec<f32>,
-    tainted_fields: &FieldTaint,
+    state: &mut State,
+    opponents: &Opponents,
+    deadline: &Deadline,
+    config: &HybridConfig,
 ) -> f32 {
     let next_position = move_by_direction(&state.my_position, d, &state.game_size);
     let use_compact_mode = empty_space.num_snake_heads <= 2
-        || evaluate_empty_space(empty_space)
-            > 0.8 * opponent_rooms
+        || evaluate_empty_space(empty_space, config)
+            > config.compact_mode_threshold
+                * opponents
+                    .rooms
                     .iter()
                     .map(|f| OrderedFloat(*f))
                     .min()
                     .unwrap_or(OrderedFloat(0.0))
                     .0;
-    info!("{}using compact mode.", if use_compact_mode {""} else {"not "});
+    info!(
+        "{}using compact mode.",
+        if use_compact_mode { "" } else { "not " }
+    );
 
-    let updated_space_score = calculate_empty_space_after_step(state, &next_position);
-    let my_space_score_change = updated_space_score/evaluate_empty_space(empty_space);
+    let updated_space_score =
+        calculate_empty_space_after_step(state, &next_position, deadline, config);
+    let my_space_score_change = updated_space_score / evaluate_empty_space(empty_space, config);
     debug!("My space score change: {:?}", my_space_score_change);
 
     if use_compact_mode {
         let mut result =
             1.0 / distance_to_next_opponent_head(&next_position, state).unwrap_or(u32::MAX) as f32;
-            result += 0.2 * (1.0 - my_space_score_change);
-        if has_wall(&next_position, &state) {
-            result -= 0.03;
+        result += config.compact_space_weight * (1.0 - my_space_score_change);
+        if has_wall(&next_position, state) {
+            result -= config.compact_wall_bonus;
         }
         result
     } else {
-        - evaluate_direction_weighted(state, &next_position, tainted_fields)
-            * my_space_score_change.powf(0.5)
-            * if has_neighbour_head(&next_position, state) { 0.85 } else { 1.0 }
+        let collision_risk = opponents.contested.probability(&next_position);
+        -evaluate_direction_weighted(state, &next_position, &opponents.taint, deadline)
+            * my_space_score_change.powf(config.space_change_exponent)
+            * (1.0 + (config.factor_head - 1.0) * collision_risk)
     }
 }
 
-
 fn evaluate_direction_weighted(
     state: &State,
     position: &Position,
     tainted_fields: &FieldTaint,
+    deadline: &Deadline,
 ) -> f32 {
     let mut result = 0.0;
-    let mut visited = std::collections::HashSet::new();
+    let mut visited = Bitboard::new(&state.game_size);
     let mut queue = std::collections::VecDeque::new();
 
-    visited.insert(position.clone());
+    visited.set(position);
     queue.push_back((1.0, position.clone()));
 
     while let Some((scale, p)) = queue.pop_front() {
+        if deadline.expired() {
+            break;
+        }
         if !state.is_occupied(p.clone()) {
             let score = tainted_fields[p.as_dim()];
             result += scale * score;
@@ -176,37 +257,43 @@ fn evaluate_direction_weighted(
                 MoveDirection::Right,
             ] {
                 let next_pos = move_by_direction(&p, &direction, &state.game_size);
-                if !visited.contains(&next_pos) {
-                    visited.insert(next_pos.clone());
+                if !visited.get(&next_pos) {
+                    visited.set(&next_pos);
                     queue.push_back((score * scale, next_pos));
                 }
             }
         }
     }
-    return result;
+    result
 }
 
-const MAX_FIELD_SCORE: f32 = 0.85;
-const MIN_FIELD_SCORE: f32 = 0.8;
-const FIELD_SCORE_ALPHA: f32 = 0.9;
-
-// IDEA: field score = 1.0 * (1 - (MIN_FIELD_SCORE ^ (alpha * distance_1))) * (1 - (MIN_FIELD_SCORE ^ (alpha * distance_2))) ...
-fn taint_fields_near_heads(state: &State) -> FieldTaint {
-    let mut result = ndarray::Array2::from_elem(state.game_size.as_dim(), MAX_FIELD_SCORE);
+// IDEA: field score = 1.0 * (1 - (min_field_score ^ (alpha * distance_1))) * (1 - (min_field_score ^ (alpha * distance_2))) ...
+fn taint_fields_near_heads(
+    state: &State,
+    deadline: &Deadline,
+    config: &HybridConfig,
+) -> FieldTaint {
+    let mut result = ndarray::Array2::from_elem(state.game_size.as_dim(), config.max_field_score);
 
     for (player, head) in state.player_heads.iter() {
         if *player == state.my_id {
             continue;
         }
 
-        let mut visited = std::collections::HashSet::new();
+        let mut visited = Bitboard::new(&state.game_size);
         let mut queue = std::collections::VecDeque::new();
 
-        visited.insert(head.clone());
+        visited.set(head);
         queue.push_back((-1i32, head.clone()));
 
         while let Some((dist, p)) = queue.pop_front() {
-            result[p.as_dim()] *= 1.0 - MIN_FIELD_SCORE.powf(dist as f32 * FIELD_SCORE_ALPHA);
+            if deadline.expired() {
+                return result;
+            }
+            result[p.as_dim()] *= 1.0
+                - config
+                    .min_field_score
+                    .powf(dist as f32 * config.field_score_alpha);
             for direction in [
                 MoveDirection::Up,
                 MoveDirection::Down,
@@ -214,22 +301,25 @@ fn taint_fields_near_heads(state: &State) -> FieldTaint {
                 MoveDirection::Right,
             ] {
                 let next_pos = move_by_direction(&p, &direction, &state.game_size);
-                if !visited.contains(&next_pos) && !state.is_occupied(next_pos.clone()) {
-                    visited.insert(next_pos.clone());
+                if !visited.get(&next_pos) && !state.is_occupied(next_pos.clone()) {
+                    visited.set(&next_pos);
                     queue.push_back((dist + 1, next_pos));
                 }
             }
         }
     }
 
-    return result;
+    result
 }
 
-fn calculate_empty_space_after_step(game_state: &State, step_to: &Position) -> f32 {
-    let mut new_state = game_state.clone();
-    new_state.field_occupation[step_to.as_dim()].replace(game_state.my_id);
-    new_state.player_heads.insert(new_state.my_id, step_to.clone());
-    new_state.my_position = step_to.clone();
+fn calculate_empty_space_after_step(
+    game_state: &mut State,
+    step_to: &Position,
+    deadline: &Deadline,
+    config: &HybridConfig,
+) -> f32 {
+    let undo = game_state.make_moves(&[(game_state.my_id, step_to.clone())]);
+    let new_state = &*game_state;
 
     let my_min_space = [
         MoveDirection::Up,
@@ -237,19 +327,29 @@ fn calculate_empty_space_after_step(game_state: &State, step_to: &Position) -> f
         MoveDirection::Left,
         MoveDirection::Right,
     ]
-        .iter()
-        .filter(|d| !new_state.is_occupied(move_by_direction(&new_state.my_position, &d, &new_state.game_size)))
-        .map(|d| {
-            OrderedFloat(evaluate_empty_space(
-                &explore_empty_space(
-                    &new_state,
-                    move_by_direction(&new_state.my_position, &d, &new_state.game_size),
-                )
-            ))
-        })
-        .min()
-        .unwrap_or(OrderedFloat(0.0))
-        .0;
-    
+    .iter()
+    .filter(|d| {
+        !new_state.is_occupied(move_by_direction(
+            &new_state.my_position,
+            d,
+            &new_state.game_size,
+        ))
+    })
+    .take_while(|_d| !deadline.expired())
+    .map(|d| {
+        OrderedFloat(evaluate_empty_space(
+            &explore_empty_space(
+                new_state,
+                move_by_direction(&new_state.my_position, d, &new_state.game_size),
+                deadline,
+            ),
+            config,
+        ))
+    })
+    .min()
+    .unwrap_or(OrderedFloat(0.0))
+    .0;
+
+    game_state.unmake_moves(undo);
     my_min_space
 }
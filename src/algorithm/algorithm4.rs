@@ -1,144 +1,286 @@
 use std::collections::HashSet;
-use std::thread::scope;
 
+use super::bitboard::Bitboard;
 use super::helper::{
-    distance_to_next_opponent_head, has_neighbour_head, has_wall, iter_directions,
-    move_by_direction,
+    contested_fields, has_wall, iter_directions, move_by_direction, ContestedFields,
 };
-use super::State;
+use super::{CandidateScore, Deadline, Decision, State, Strategy};
 use crate::client::PlayerId;
-use crate::{AlgorithmConfig, Command, MoveDirection, Position};
+use crate::{MoveDirection, Position};
 use log::{info, warn};
 use ordered_float::OrderedFloat;
-use rand::rngs::ThreadRng;
+use rand::rngs::StdRng;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-pub fn decide_action(
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct RankedConfig {
+    /// Weight of a free field per step of distance in the wide room bonus
+    pub wide_room_decay: f32,
+    /// Divisor of the wide room bonus
+    pub wide_room_divisor: f32,
+    /// Subtracted from the direction score if the target field is next to a wall
+    pub wall_bonus: f32,
+    /// Exponent of the number of players whose trails bound an empty space, multiplying its size
+    pub bounding_snakes_exponent: f32,
+    /// Exponent of the survival probability when weighting the space score after a step. The
+    /// higher, the more space it takes to make a head-on collision worth risking.
+    pub risk_aversion: f32,
+}
+
+impl Default for RankedConfig {
+    fn default() -> Self {
+        RankedConfig {
+            wide_room_decay: 0.75,
+            wide_room_divisor: 20.0,
+            wall_bonus: 0.3,
+            bounding_snakes_exponent: 0.25,
+            risk_aversion: 3.0,
+        }
+    }
+}
+
+/// Ranks directions by head proximity, space after the next step and a direction score.
+pub struct Ranked {
+    config: RankedConfig,
+}
+
+impl Ranked {
+    pub fn new(config: RankedConfig) -> Self {
+        Ranked { config }
+    }
+}
+
+impl Strategy for Ranked {
+    fn decide(&mut self, state: &mut State, rng: &mut StdRng, deadline: &Deadline) -> Decision {
+        decide_action(state, rng, deadline, &self.config)
+    }
+}
+
+fn decide_action(
     state: &mut State,
-    rng: &mut ThreadRng,
-    _config: &AlgorithmConfig,
-) -> Option<Command<'static>> {
-    let mut directions = [
+    rng: &mut StdRng,
+    deadline: &Deadline,
+    config: &RankedConfig,
+) -> Decision {
+    let possible_directions = [
         MoveDirection::Up,
         MoveDirection::Down,
         MoveDirection::Left,
         MoveDirection::Right,
     ]
     .iter()
-    .filter(|d| !state.is_occupied(move_by_direction(&state.my_position, &d, &state.game_size)))
-    .map(|d| (d, rank_direction(d, state, rng)))
+    .filter(|d| !state.is_occupied(move_by_direction(&state.my_position, d, &state.game_size)))
     .collect::<Vec<_>>();
+    let contested = contested_fields(state);
+    let mut directions = possible_directions
+        .into_iter()
+        .take_while(|_d| !deadline.expired())
+        .map(|d| {
+            (
+                d,
+                rank_direction(d, state, &contested, rng, deadline, config),
+            )
+        })
+        .collect::<Vec<_>>();
 
-    directions.sort_by_key(|(_d, rank)| rank.clone());
+    directions.sort_by_key(|(_d, (rank, _candidate))| rank.clone());
     if directions.is_empty() {
         warn!("No step possible.");
-        None
+    } else if directions.len() == 1 {
+        info!("Only one step possible.");
     } else {
-        if directions.len() == 1 {
-            info!("Only one step possible.");
-        } else if directions[0].1.has_neighbour_head != directions[1].1.has_neighbour_head {
-            info!("Avoiding other head");
-        } else if directions[0].1.best_empty_space_score_after_step != directions[1].1.best_empty_space_score_after_step {
-            info!("Room score different: {:?}: {}, {:?}: {}", directions[0].0, directions[0].1.best_empty_space_score_after_step.0, directions[1].0, directions[1].1.best_empty_space_score_after_step.0);
-        } else if directions[0].1.direction_score != directions[1].1.direction_score {
-            info!("Better direction: {:?}: {}, {:?}: {}", directions[0].0, directions[0].1.direction_score.0, directions[1].0, directions[1].1.direction_score.0);
+        let (first, second) = (&(directions[0].1).0, &(directions[1].1).0);
+        if first.collision_risk != second.collision_risk {
+            info!(
+                "Collision risk different: {:?}: {}, {:?}: {}",
+                directions[0].0, first.collision_risk.0, directions[1].0, second.collision_risk.0
+            );
+        }
+        if first.best_empty_space_score_after_step != second.best_empty_space_score_after_step {
+            info!(
+                "Room score different: {:?}: {}, {:?}: {}",
+                directions[0].0,
+                first.best_empty_space_score_after_step.0,
+                directions[1].0,
+                second.best_empty_space_score_after_step.0
+            );
+        } else if first.direction_score != second.direction_score {
+            info!(
+                "Better direction: {:?}: {}, {:?}: {}",
+                directions[0].0, first.direction_score.0, directions[1].0, second.direction_score.0
+            );
         } else {
             info!("Using random direction");
         }
-    Some(Command::Move(directions[0].0.clone()))
     }
+    Decision::from_candidates(
+        directions
+            .into_iter()
+            .map(|(_d, (_rank, candidate))| candidate)
+            .collect(),
+    )
 }
 
-
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
 struct DirectionRanking {
-    has_neighbour_head: bool,
+    /// Space score after the step, weighted by the probability of surviving the step
     best_empty_space_score_after_step: OrderedFloat<f32>,
+    /// Probability of a head-on collision in this step, preferring the safer of two equally good
+    /// moves
+    collision_risk: OrderedFloat<f32>,
     direction_score: OrderedFloat<f32>,
     random: i32,
 }
 
-fn rank_direction(d: &MoveDirection, state: &State, rng: &mut ThreadRng) -> DirectionRanking {
-    let next_position = move_by_direction(&state.my_position, &d, &state.game_size);
-    let current_space = explore_empty_space(state, next_position.clone());
-    DirectionRanking {
-        has_neighbour_head: has_neighbour_head(&next_position, state) && state.player_heads.len() > 2,
-        best_empty_space_score_after_step: OrderedFloat(calculate_best_empty_space_after_step(
-            state,
-            &next_position),
+fn rank_direction(
+    d: &MoveDirection,
+    state: &mut State,
+    contested: &ContestedFields,
+    rng: &mut StdRng,
+    deadline: &Deadline,
+    config: &RankedConfig,
+) -> (DirectionRanking, CandidateScore) {
+    let next_position = move_by_direction(&state.my_position, d, &state.game_size);
+    let current_space = explore_empty_space(state, next_position.clone(), deadline, config);
+    let (head_proximity, wall_bonus, wide_room_bonus) =
+        evaluate_direction(&next_position, &current_space, state, config);
+    // A head-on collision with the last opponent is a draw, so it is not worth avoiding
+    let collision_risk = if state.player_heads.len() > 2 {
+        contested.probability(&next_position)
+    } else {
+        0.0
+    };
+    let ranking = DirectionRanking {
+        best_empty_space_score_after_step: OrderedFloat(
+            calculate_best_empty_space_after_step(state, &next_position, deadline, config)
+                * (1.0 - collision_risk).powf(config.risk_aversion),
         ),
-        direction_score: OrderedFloat(evaluate_direction(&next_position, &current_space, state)),
+        collision_risk: OrderedFloat(collision_risk),
+        direction_score: OrderedFloat(head_proximity - wall_bonus - wide_room_bonus),
         random: rng.gen(),
-    }
+    };
+    let candidate = CandidateScore {
+        space_score: Some(ranking.best_empty_space_score_after_step.0),
+        direction_score: Some(ranking.direction_score.0),
+        head_proximity: Some(head_proximity),
+        collision_risk: Some(collision_risk),
+        wall_bonus: Some(wall_bonus),
+        random: Some(ranking.random),
+        ..CandidateScore::new(d.clone())
+    };
+    (ranking, candidate)
 }
 
-fn calculate_best_empty_space_after_step(game_state: &State, step_to: &Position) -> f32 {
-    let new_state = game_state.simulate_step(step_to);
+fn calculate_best_empty_space_after_step(
+    game_state: &mut State,
+    step_to: &Position,
+    deadline: &Deadline,
+    config: &RankedConfig,
+) -> f32 {
+    let undo = game_state.make_moves(&[(game_state.my_id, step_to.clone())]);
+    let result = best_empty_space_score(game_state, deadline, config);
+    game_state.unmake_moves(undo);
+    result
+}
 
-    let my_min_space = iter_directions()
-        .map(|d| move_by_direction(&new_state.my_position, d, &new_state.game_size))
-        .filter(|p| !new_state.is_occupied(p.clone()))
-        .map(|p| OrderedFloat(evaluate_empty_space(&explore_empty_space(&new_state, p))))
+/// Space score of the best room next to our head, 0 if there is no free field next to it
+pub(super) fn best_empty_space_score(
+    state: &State,
+    deadline: &Deadline,
+    config: &RankedConfig,
+) -> f32 {
+    iter_directions()
+        .map(|d| move_by_direction(&state.my_position, d, &state.game_size))
+        .filter(|p| !state.is_occupied(p.clone()))
+        .take_while(|_p| !deadline.expired())
+        .map(|p| {
+            OrderedFloat(evaluate_empty_space(
+                &explore_empty_space(state, p, deadline, config),
+                config,
+            ))
+        })
         .min()
         .unwrap_or(OrderedFloat(0.0))
-        .0;
-
-    my_min_space
+        .0
 }
 
 #[derive(Debug, Default)]
 struct EmptySpaceState {
     size: usize,
     snake_head_distances: Vec<usize>,
-    sum_x: u32,
     bounding_snakes: HashSet<PlayerId>,
     wide_room_score: f32,
-    sum_y: u32,
 }
 
-fn explore_empty_space(state: &State, position: Position) -> EmptySpaceState {
+fn explore_empty_space(
+    state: &State,
+    position: Position,
+    deadline: &Deadline,
+    config: &RankedConfig,
+) -> EmptySpaceState {
     let mut result = EmptySpaceState::default();
-    let mut visited = std::collections::HashSet::new();
-    let mut queue = std::collections::VecDeque::new();
-
-    visited.insert(position.clone());
-    queue.push_back((0usize, position.clone()));
-
-    while let Some((dist, p)) = queue.pop_front() {
-        if state.player_heads.values().any(|head| *head == p) {
-            if state.field_occupation.get(p.as_dim()) != Some(&Some(state.my_id))
-            {
-                result.snake_head_distances.push(dist);
-            }
+    let free = state.free_fields();
+    let opponent_heads = Bitboard::from_positions(
+        &state.game_size,
+        state
+            .player_heads
+            .values()
+            .filter(|head| state.field_occupation[head.as_dim()] != Some(state.my_id)),
+    );
+
+    let start = Bitboard::from_positions(&state.game_size, [&position]);
+    for (dist, mut layer) in Bitboard::layers(&start, &free)
+        .enumerate()
+        .take_while(|_layer| !deadline.expired())
+    {
+        for _ in 0..opponent_heads.count_common(&layer) {
+            result.snake_head_distances.push(dist);
         }
-        if !state.is_occupied(p.clone()) {
-            result.size += 1;
-            result.sum_x += p.x;
-            result.sum_y += p.y;
-            result.wide_room_score += 0.75f32.powf(dist as f32);
-            for direction in iter_directions() {
-                let next_pos = move_by_direction(&p, &direction, &state.game_size);
-                if !visited.contains(&next_pos) {
-                    visited.insert(next_pos.clone());
-                    queue.push_back((dist + 1, next_pos));
-                }
-            }
-        } else {
-            result.bounding_snakes.insert(state.field_occupation.get(p.as_dim()).unwrap().unwrap().clone());
+        let free_fields = layer.count_common(&free);
+        result.size += free_fields;
+        for _ in 0..free_fields {
+            result.wide_room_score += config.wide_room_decay.powf(dist as f32);
+        }
+        layer.remove(&free);
+        for p in layer.iter() {
+            result
+                .bounding_snakes
+                .insert(state.field_occupation[p.as_dim()].unwrap());
         }
     }
-    return result;
+    result
 }
 
-fn evaluate_empty_space(state: &EmptySpaceState) -> f32 {
-    -(state.size as f32) * (state.bounding_snakes.len() as f32).powf(0.25) / (state.snake_head_distances.len() as f32 + 1.0).sqrt()
+fn evaluate_empty_space(state: &EmptySpaceState, config: &RankedConfig) -> f32 {
+    -(state.size as f32)
+        * (state.bounding_snakes.len() as f32).powf(config.bounding_snakes_exponent)
+        / (state.snake_head_distances.len() as f32 + 1.0).sqrt()
 }
 
-fn evaluate_direction(pos: &Position, space: &EmptySpaceState, state: &State) -> f32 {
-    info!("Wide space score: {}", space.wide_room_score / 20.0);
-    space.snake_head_distances.iter()
-        .map(|dist| 1.0 / *dist as f32)
-        .sum::<f32>()
-    - if has_wall(pos, state) { 0.3 } else { 0.0 }
-    - space.wide_room_score / 20.0
+/// Components of the direction score: proximity of opponent heads, wall bonus and wide room bonus
+fn evaluate_direction(
+    pos: &Position,
+    space: &EmptySpaceState,
+    state: &State,
+    config: &RankedConfig,
+) -> (f32, f32, f32) {
+    info!(
+        "Wide space score: {}",
+        space.wide_room_score / config.wide_room_divisor
+    );
+    (
+        space
+            .snake_head_distances
+            .iter()
+            .map(|dist| 1.0 / *dist as f32)
+            .sum::<f32>(),
+        if has_wall(pos, state) {
+            config.wall_bonus
+        } else {
+            0.0
+        },
+        space.wide_room_score / config.wide_room_divisor,
+    )
 }
@@ -0,0 +1,332 @@
+use super::algorithm4::{best_empty_space_score, RankedConfig};
+use super::bitboard::Bitboard;
+use super::helper::{iter_directions, move_by_direction, resolve_collisions};
+use super::{CandidateScore, Deadline, Decision, State, Strategy};
+use crate::client::PlayerId;
+use crate::{MoveDirection, Position};
+use log::{debug, info};
+use ordered_float::OrderedFloat;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+
+/// How the opponents' moves are chosen in the search tree
+#[derive(Deserialize, Serialize, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// All opponents minimise our score
+    #[default]
+    Paranoid,
+    /// Every player maximises its own score
+    MaxN,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct SearchConfig {
+    pub mode: SearchMode,
+    /// Maximum number of rounds of simultaneous moves to look ahead
+    pub max_depth: u32,
+    /// Opponents whose heads are further away from our head (in steps through empty fields) are
+    /// ignored in the search
+    pub opponent_distance: u32,
+    /// Maximum number of the nearest opponents taking part in the search
+    pub max_opponents: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            mode: SearchMode::Paranoid,
+            max_depth: 3,
+            opponent_distance: 6,
+            max_opponents: 1,
+        }
+    }
+}
+
+/// Searches several rounds of simultaneous moves of us and the nearest opponents with iterative
+/// deepening. Leaves are scored by the best room next to each player's head, like [Ranked] scores
+/// its moves.
+///
+/// [Ranked]: super::algorithm4::Ranked
+pub struct Search {
+    config: SearchConfig,
+    /// Parameters of the room scores
+    ranked: RankedConfig,
+}
+
+impl Search {
+    pub fn new(config: SearchConfig, ranked: RankedConfig) -> Self {
+        Search { config, ranked }
+    }
+}
+
+impl Strategy for Search {
+    fn decide(&mut self, state: &mut State, _rng: &mut StdRng, deadline: &Deadline) -> Decision {
+        let players = involved_players(state, &self.config);
+        debug!("Searching with players {:?}", players);
+        let tree = SearchTree {
+            players: &players,
+            mode: self.config.mode,
+            ranked: &self.ranked,
+            deadline,
+        };
+
+        let mut scores = Vec::new();
+        let mut timed_out = false;
+        for depth in 1..=self.config.max_depth {
+            match tree.search_root(state, depth) {
+                Some(result) => scores = result,
+                None => {
+                    info!("Search aborted at depth {}", depth);
+                    timed_out = true;
+                    break;
+                }
+            }
+        }
+        scores.sort_by_key(|(_d, score)| OrderedFloat(-*score));
+        let candidates = scores
+            .into_iter()
+            .map(|(d, score)| CandidateScore {
+                space_score: Some(-score),
+                ..CandidateScore::new(d)
+            })
+            .collect();
+        Decision {
+            timed_out,
+            ..Decision::from_candidates(candidates)
+        }
+    }
+}
+
+/// Our own player followed by the nearest opponents within the configured distance
+fn involved_players(state: &State, config: &SearchConfig) -> Vec<PlayerId> {
+    let mut players = vec![state.my_id];
+    let start = Bitboard::from_positions(&state.game_size, [&state.my_position]);
+    let mut expandable = state.free_fields();
+    expandable |= &start;
+    for layer in Bitboard::layers(&start, &expandable).take(config.opponent_distance as usize + 1) {
+        for (player, head) in state.player_heads.iter() {
+            if *player != state.my_id && layer.get(head) && players.len() <= config.max_opponents {
+                players.push(*player);
+            }
+        }
+    }
+    players
+}
+
+/// Score of a player which died in the given round. Dying later is better.
+fn death_score(round: u32) -> f32 {
+    -1_000_000.0 + round as f32
+}
+
+struct SearchTree<'a> {
+    /// Players taking part in the search, our own player first
+    players: &'a [PlayerId],
+    mode: SearchMode,
+    ranked: &'a RankedConfig,
+    deadline: &'a Deadline,
+}
+
+impl SearchTree<'_> {
+    /// Our score for each of our possible moves when searching `depth` rounds, or None if the
+    /// deadline expired.
+    fn search_root(&self, state: &mut State, depth: u32) -> Option<Vec<(MoveDirection, f32)>> {
+        let died = vec![None; self.players.len()];
+        let mut result = Vec::new();
+        for d in iter_directions() {
+            let target = move_by_direction(&state.my_position, d, &state.game_size);
+            if state.is_occupied(target.clone()) {
+                continue;
+            }
+            let mut moves = vec![None; self.players.len()];
+            moves[0] = Some(target);
+            let scores = self.choose_move(state, &died, &mut moves, 1, depth, 1)?;
+            result.push((d.clone(), scores[0]));
+        }
+        Some(result)
+    }
+
+    /// Choose the move of the player at `index` in the current round, after the players before
+    /// it have chosen their `moves`. `died` contains the round in which a player died, if any.
+    fn choose_move(
+        &self,
+        state: &mut State,
+        died: &[Option<u32>],
+        moves: &mut Vec<Option<Position>>,
+        index: usize,
+        depth: u32,
+        round: u32,
+    ) -> Option<Vec<f32>> {
+        if self.deadline.expired() {
+            return None;
+        }
+        if index == self.players.len() {
+            return self.play_round(state, died, moves, depth, round);
+        }
+        let head = match state.player_head(self.players[index]) {
+            Some(head) if died[index].is_none() => head.clone(),
+            _ => return self.choose_move(state, died, moves, index + 1, depth, round),
+        };
+
+        let mut best: Option<Vec<f32>> = None;
+        for d in iter_directions() {
+            let target = move_by_direction(&head, d, &state.game_size);
+            if state.is_occupied(target.clone()) {
+                continue;
+            }
+            moves[index] = Some(target);
+            let scores = self.choose_move(state, died, moves, index + 1, depth, round)?;
+            let better = match (&best, self.mode) {
+                (None, _) => true,
+                (Some(best), _) if index == 0 => scores[0] > best[0],
+                (Some(best), SearchMode::Paranoid) => scores[0] < best[0],
+                (Some(best), SearchMode::MaxN) => scores[index] > best[index],
+            };
+            if better {
+                best = Some(scores);
+            }
+        }
+        moves[index] = None;
+        match best {
+            Some(scores) => Some(scores),
+            // No possible move, so the player dies in this round
+            None => self.choose_move(state, died, moves, index + 1, depth, round),
+        }
+    }
+
+    /// Apply the chosen moves of all players simultaneously, remove the players which died like
+    /// the server does and continue with the next round.
+    fn play_round(
+        &self,
+        state: &mut State,
+        died: &[Option<u32>],
+        moves: &[Option<Position>],
+        depth: u32,
+        round: u32,
+    ) -> Option<Vec<f32>> {
+        let mut died = died.to_vec();
+        resolve_collisions(moves, &mut died, round);
+        let undo = state.make_moves(
+            &self
+                .players
+                .iter()
+                .zip(moves.iter())
+                .filter_map(|(player, target)| target.clone().map(|t| (*player, t)))
+                .collect::<Vec<_>>(),
+        );
+        let dead: Vec<PlayerId> = self
+            .players
+            .iter()
+            .zip(died.iter())
+            .filter(|(_player, died)| **died == Some(round))
+            .map(|(player, _died)| *player)
+            .collect();
+        let removed = state.remove_players(&dead);
+
+        // Without opponents, our own moves are still searched to find dead ends
+        let result = if depth <= 1 || died[0].is_some() {
+            Some(self.evaluate(state, &died))
+        } else {
+            let mut moves = vec![None; self.players.len()];
+            self.choose_move(state, &died, &mut moves, 0, depth - 1, round + 1)
+        };
+        state.unmake_moves(removed);
+        state.unmake_moves(undo);
+        result
+    }
+
+    /// Score of the best room next to the head of each player, higher is better
+    fn evaluate(&self, state: &mut State, died: &[Option<u32>]) -> Vec<f32> {
+        // Our head is gone if we died, so switching back does not restore our position
+        let my_position = state.my_position.clone();
+        let scores = self
+            .players
+            .iter()
+            .zip(died.iter())
+            .map(|(player, died)| match (died, state.player_head(*player)) {
+                (None, Some(_head)) => {
+                    let me = state.switch_player(*player);
+                    let score = -best_empty_space_score(state, self.deadline, self.ranked);
+                    state.switch_player(me);
+                    score
+                }
+                (Some(round), _) => death_score(*round),
+                (None, None) => death_score(0),
+            })
+            .collect();
+        state.my_position = my_position;
+        scores
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::scenario::parse;
+    use crate::client::Answer;
+    use rand::SeedableRng;
+
+    /// A state with the given heads, the first one ours, and trails of other fields
+    fn state(size: u32, heads: &[(u32, u32)], trails: &[(u32, u32)]) -> State {
+        let mut state = State::default();
+        let size = Position { x: size, y: size };
+        state.update_from_answer(&Answer::Game(size, PlayerId(0)));
+        for (x, y) in trails {
+            state.update_from_answer(&Answer::Pos(PlayerId(9), Position { x: *x, y: *y }));
+        }
+        for (i, (x, y)) in heads.iter().enumerate() {
+            state.update_from_answer(&Answer::Pos(PlayerId(i as u32), Position { x: *x, y: *y }));
+        }
+        state
+    }
+
+    #[test]
+    fn evaluate_scores_rooms_like_ranked() {
+        // A wall splits the board into a room of 3 columns for us and of 4 columns for the opponent
+        let wall: Vec<(u32, u32)> = (0..9).flat_map(|y| [(0, y), (4, y)]).collect();
+        let mut state = state(9, &[(2, 3), (6, 3)], &wall);
+        let players = [PlayerId(0), PlayerId(1)];
+        let tree = SearchTree {
+            players: &players,
+            mode: SearchMode::Paranoid,
+            ranked: &RankedConfig::default(),
+            deadline: &Deadline::none(),
+        };
+        let scores = tree.evaluate(&mut state, &[None, None]);
+        assert_eq!(
+            scores[0],
+            -best_empty_space_score(&state, tree.deadline, tree.ranked)
+        );
+        assert!(scores[1] > scores[0]);
+        assert_eq!(state.my_id(), PlayerId(0));
+        assert_eq!(state.my_position, Position { x: 2, y: 3 });
+
+        let scores = tree.evaluate(&mut state, &[None, Some(2)]);
+        assert_eq!(scores[1], death_score(2));
+    }
+
+    #[test]
+    fn searches_own_moves_without_opponents() {
+        // The opponent is walled in, so only our moves are searched. Moving left ends in a dead end
+        // which only a search deeper than one round sees.
+        let mut state = parse(
+            "
+            aaaaaaaa
+            a.@.....
+            aaaaaaaa
+            aaaaaaAa
+            aaaaaaaa
+            ",
+        );
+        let mut search = Search::new(SearchConfig::default(), RankedConfig::default());
+        let decision = search.decide(&mut state, &mut StdRng::seed_from_u64(0), &Deadline::none());
+        assert_eq!(decision.direction, Some(MoveDirection::Right));
+        let left = decision
+            .candidates
+            .iter()
+            .find(|c| c.direction == MoveDirection::Left)
+            .unwrap();
+        assert_eq!(left.space_score, Some(-death_score(2)));
+    }
+}
@@ -0,0 +1,65 @@
+use super::helper::{move_by_direction, voronoi};
+use super::{CandidateScore, Deadline, Decision, State, Strategy};
+use crate::MoveDirection;
+use log::debug;
+use ordered_float::OrderedFloat;
+use rand::rngs::StdRng;
+
+/// Chooses the direction which maximises the fields we reach before any opponent, minus the
+/// territory of the strongest opponent.
+pub struct Territory;
+
+impl Strategy for Territory {
+    fn decide(&mut self, state: &mut State, rng: &mut StdRng, deadline: &Deadline) -> Decision {
+        decide_action(state, rng, deadline)
+    }
+}
+
+fn decide_action(state: &mut State, _rng: &mut StdRng, deadline: &Deadline) -> Decision {
+    let possible_directions = [
+        MoveDirection::Up,
+        MoveDirection::Down,
+        MoveDirection::Left,
+        MoveDirection::Right,
+    ]
+    .iter()
+    .filter(|d| !state.is_occupied(move_by_direction(&state.my_position, d, &state.game_size)))
+    .collect::<Vec<_>>();
+    let mut directions = possible_directions
+        .into_iter()
+        .take_while(|_d| !deadline.expired())
+        .map(|d| evaluate_direction(d, state, deadline))
+        .collect::<Vec<_>>();
+
+    directions.sort_by_key(|c| {
+        (
+            OrderedFloat(c.space_score.unwrap()),
+            OrderedFloat(c.direction_score.unwrap()),
+        )
+    });
+    debug!("Directions: {:?}", directions);
+    Decision::from_candidates(directions)
+}
+
+/// Our territory after the step minus the largest opponent territory as space score, our own
+/// territory as direction score. Both are negated, so lower is better.
+fn evaluate_direction(d: &MoveDirection, state: &mut State, deadline: &Deadline) -> CandidateScore {
+    let target = move_by_direction(&state.my_position, d, &state.game_size);
+    let undo = state.make_moves(&[(state.my_id, target)]);
+    let partition = voronoi(state, deadline);
+    state.unmake_moves(undo);
+
+    let mine = partition.territory_size(state.my_id) as f32;
+    let best_opponent = partition
+        .territories
+        .keys()
+        .filter(|player| **player != state.my_id)
+        .map(|player| partition.territory_size(*player))
+        .max()
+        .unwrap_or(0) as f32;
+    CandidateScore {
+        space_score: Some(best_opponent - mine),
+        direction_score: Some(-mine),
+        ..CandidateScore::new(d.clone())
+    }
+}
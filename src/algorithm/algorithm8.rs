@@ -0,0 +1,535 @@
+//! Monte Carlo tree search over the simultaneous moves of all players.
+//!
+//! Every node of the tree keeps separate move statistics for each player (decoupled UCT): the
+//! players choose their moves independently by UCB1, and the resulting joint move leads to the
+//! child node. Below the tree, the game is played out by a rollout policy for a limited number of
+//! rounds. Players are rewarded for surviving and for the territory they hold in the end.
+
+use super::helper::{iter_directions, move_by_direction, resolve_collisions, voronoi};
+use super::{
+    create_strategy, CandidateScore, Deadline, Decision, State, Strategy, Undo, UnknownStrategy,
+};
+use crate::client::PlayerId;
+use crate::{AlgorithmConfig, MoveDirection, Position};
+use log::debug;
+use ordered_float::OrderedFloat;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Share of the time until the tick deadline spent on playouts. The rest is left for finishing
+/// the last playout and sending the move.
+const SEARCH_SHARE: f64 = 0.8;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct MctsConfig {
+    /// Maximum number of playouts per tick
+    pub iterations: u32,
+    /// Maximum time in milliseconds to search per tick. With a tick budget, the search also stops
+    /// a safety margin before the end of the budget.
+    pub time_limit_ms: Option<u64>,
+    /// Number of rounds played by the rollout policy below the tree
+    pub rollout_depth: u32,
+    /// Strategy choosing the moves of all players in rollouts, "random" for random moves to free
+    /// fields, "model" for opponents moving as predicted by their models or the name of any other
+    /// strategy except "mcts"
+    pub rollout_policy: String,
+    /// Exploration constant of UCB1
+    pub exploration: f32,
+    /// Seed for the random choices of the search, the same in every game. Without a seed, the
+    /// search uses the random number generator of the game.
+    pub seed: Option<u64>,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        MctsConfig {
+            iterations: 2000,
+            time_limit_ms: None,
+            rollout_depth: 10,
+            rollout_policy: "random".to_owned(),
+            exploration: 1.4,
+            seed: None,
+        }
+    }
+}
+
+pub struct Mcts {
+    config: MctsConfig,
+    policy: RolloutPolicy,
+    /// Random number generator seeded from the config, if any
+    rng: Option<StdRng>,
+}
+
+impl Mcts {
+    /// Create the search with the `mcts` section of the config. A strategy used as rollout policy
+    /// gets the rest of the config.
+    pub fn new(config: &AlgorithmConfig) -> Result<Self, UnknownStrategy> {
+        let policy = RolloutPolicy::new(&config.mcts.rollout_policy, config)?;
+        let config = config.mcts.clone();
+        let rng = config.seed.map(StdRng::seed_from_u64);
+        Ok(Mcts {
+            config,
+            policy,
+            rng,
+        })
+    }
+}
+
+impl Strategy for Mcts {
+    fn init(&mut self, state: &State) {
+        // Every game is played the same way for a given seed
+        self.rng = self.config.seed.map(StdRng::seed_from_u64);
+        if let RolloutPolicy::Strategy(strategy) = &mut self.policy {
+            strategy.init(state);
+        }
+    }
+
+    fn decide(&mut self, state: &mut State, rng: &mut StdRng, deadline: &Deadline) -> Decision {
+        let limit = match self.config.time_limit_ms {
+            Some(ms) => Deadline::after(Duration::from_millis(ms)),
+            None => Deadline::none(),
+        };
+        let limit = limit.earlier(&deadline.share(SEARCH_SHARE));
+        let mut players: Vec<PlayerId> = state
+            .player_heads
+            .keys()
+            .copied()
+            .filter(|player| *player != state.my_id)
+            .collect();
+        players.sort_unstable();
+        players.insert(0, state.my_id);
+        let mut tree = Tree {
+            nodes: vec![Node::new(state, &players, &vec![None; players.len()])],
+            players,
+            exploration: self.config.exploration,
+        };
+
+        let rng = match self.rng.as_mut() {
+            Some(seeded) => seeded,
+            None => rng,
+        };
+        let mut iterations = 0;
+        while iterations < self.config.iterations && !limit.expired() {
+            let mut playout = Playout {
+                policy: &mut self.policy,
+                rng: &mut *rng,
+                deadline: &limit,
+            };
+            playout.run(&mut tree, state, self.config.rollout_depth);
+            iterations += 1;
+        }
+        debug!("{} playouts", iterations);
+
+        let mut candidates: Vec<_> = tree.nodes[0].moves[0]
+            .iter()
+            .map(|stats| CandidateScore {
+                space_score: Some(-stats.mean_reward()),
+                direction_score: Some(-(stats.visits as f32)),
+                ..CandidateScore::new(stats.direction.clone())
+            })
+            .collect();
+        // The most visited move is the most robust choice
+        candidates.sort_by_key(|c| {
+            (
+                OrderedFloat(c.direction_score.unwrap()),
+                OrderedFloat(c.space_score.unwrap()),
+            )
+        });
+        Decision::from_candidates(candidates)
+    }
+
+    fn on_game_end(&mut self, state: &State, won: bool) {
+        if let RolloutPolicy::Strategy(strategy) = &mut self.policy {
+            strategy.on_game_end(state, won);
+        }
+    }
+}
+
+/// How the moves of all players are chosen below the tree
+enum RolloutPolicy {
+    /// A random move to a free field
+    Random,
+    /// Opponents move as predicted by their models, we move randomly
+    Model,
+    /// The move one of the other strategies would choose from the player's perspective
+    Strategy(Box<dyn Strategy>),
+}
+
+impl RolloutPolicy {
+    /// The policy of the given name. Strategies are created with the parameters of `config`.
+    fn new(name: &str, config: &AlgorithmConfig) -> Result<Self, UnknownStrategy> {
+        match name {
+            "random" => Ok(RolloutPolicy::Random),
+            "model" => Ok(RolloutPolicy::Model),
+            // The search can't be its own rollout policy
+            "mcts" => Err(UnknownStrategy::RolloutPolicy(name.to_owned())),
+            _ => {
+                let config = AlgorithmConfig {
+                    algorithm: name.to_owned(),
+                    endgame: false,
+                    ..config.clone()
+                };
+                create_strategy(&config)
+                    .map(RolloutPolicy::Strategy)
+                    .map_err(|_e| UnknownStrategy::RolloutPolicy(name.to_owned()))
+            }
+        }
+    }
+}
+
+struct MoveStats {
+    direction: MoveDirection,
+    target: Position,
+    visits: u32,
+    total_reward: f32,
+}
+
+impl MoveStats {
+    fn mean_reward(&self) -> f32 {
+        if self.visits == 0 {
+            0.0
+        } else {
+            self.total_reward / self.visits as f32
+        }
+    }
+}
+
+struct Node {
+    visits: u32,
+    /// Statistics of the possible moves of each player, empty if the player is dead or has no
+    /// free field to move to
+    moves: Vec<Vec<MoveStats>>,
+    /// Child nodes by the index of the move chosen by each player
+    children: HashMap<Vec<Option<usize>>, usize>,
+}
+
+impl Node {
+    fn new(state: &State, players: &[PlayerId], died: &[Option<u32>]) -> Self {
+        let moves = players
+            .iter()
+            .zip(died.iter())
+            .map(|(player, died)| match (died, state.player_head(*player)) {
+                (None, Some(head)) => {
+                    let mut moves: Vec<MoveStats> = Vec::with_capacity(4);
+                    for d in iter_directions() {
+                        let target = move_by_direction(head, d, &state.game_size);
+                        // Different directions lead to the same field on tiny boards
+                        if !state.is_occupied(target.clone())
+                            && !moves.iter().any(|m| m.target == target)
+                        {
+                            moves.push(MoveStats {
+                                direction: d.clone(),
+                                target,
+                                visits: 0,
+                                total_reward: 0.0,
+                            });
+                        }
+                    }
+                    moves
+                }
+                _ => Vec::new(),
+            })
+            .collect();
+        Node {
+            visits: 0,
+            moves,
+            children: HashMap::new(),
+        }
+    }
+
+    /// Index of the move of the player at `index` by UCB1, trying every move once first
+    fn select(&self, index: usize, exploration: f32, rng: &mut StdRng) -> Option<usize> {
+        let moves = &self.moves[index];
+        let unvisited: Vec<usize> = (0..moves.len()).filter(|i| moves[*i].visits == 0).collect();
+        if let Some(i) = unvisited.choose(rng) {
+            return Some(*i);
+        }
+        let log_visits = (self.visits as f32).ln();
+        (0..moves.len()).max_by_key(|i| {
+            let stats = &moves[*i];
+            OrderedFloat(
+                stats.mean_reward() + exploration * (log_visits / stats.visits as f32).sqrt(),
+            )
+        })
+    }
+
+    fn update(&mut self, joint_move: &[Option<usize>], rewards: &[f32]) {
+        self.visits += 1;
+        for (i, choice) in joint_move.iter().enumerate() {
+            if let Some(choice) = choice {
+                let stats = &mut self.moves[i][*choice];
+                stats.visits += 1;
+                stats.total_reward += rewards[i];
+            }
+        }
+    }
+}
+
+struct Tree {
+    /// Players taking part in the search, our own player first
+    players: Vec<PlayerId>,
+    /// All nodes, the root first
+    nodes: Vec<Node>,
+    exploration: f32,
+}
+
+/// Everything needed to play out one path through the tree and the rollout below it
+struct Playout<'a> {
+    policy: &'a mut RolloutPolicy,
+    rng: &'a mut StdRng,
+    deadline: &'a Deadline,
+}
+
+impl Playout<'_> {
+    /// Select a path through the tree, add a node at its end, play out the game from there and
+    /// update the statistics along the path with the result.
+    fn run(&mut self, tree: &mut Tree, state: &mut State, rollout_depth: u32) {
+        let mut died = vec![None; tree.players.len()];
+        let mut undos = Vec::new();
+        let mut path = Vec::new();
+        let mut round = 1;
+
+        let mut node = 0;
+        loop {
+            let joint_move: Vec<Option<usize>> = (0..tree.players.len())
+                .map(|i| tree.nodes[node].select(i, tree.exploration, self.rng))
+                .collect();
+            let moves: Vec<Option<Position>> = joint_move
+                .iter()
+                .zip(tree.nodes[node].moves.iter())
+                .map(|(choice, moves)| choice.map(|c| moves[c].target.clone()))
+                .collect();
+            play_round(state, &tree.players, &moves, &mut died, round, &mut undos);
+            round += 1;
+            path.push((node, joint_move.clone()));
+
+            let finished = is_finished(&died);
+            match tree.nodes[node].children.get(&joint_move) {
+                Some(child) if !finished => node = *child,
+                Some(_child) => break,
+                None => {
+                    if !finished {
+                        let child = Node::new(state, &tree.players, &died);
+                        tree.nodes.push(child);
+                        let index = tree.nodes.len() - 1;
+                        tree.nodes[node].children.insert(joint_move, index);
+                    }
+                    break;
+                }
+            }
+        }
+
+        for _ in 0..rollout_depth {
+            if is_finished(&died) || self.deadline.expired() {
+                break;
+            }
+            let moves: Vec<Option<Position>> = tree
+                .players
+                .iter()
+                .zip(died.iter())
+                .map(|(player, died)| match died {
+                    Some(_round) => None,
+                    None => self.rollout_move(state, *player),
+                })
+                .collect();
+            play_round(state, &tree.players, &moves, &mut died, round, &mut undos);
+            round += 1;
+        }
+
+        let rewards = rewards(state, &tree.players, &died, round, self.deadline);
+        for undo in undos.into_iter().rev() {
+            state.unmake_moves(undo);
+        }
+        for (node, joint_move) in path {
+            tree.nodes[node].update(&joint_move, &rewards);
+        }
+    }
+
+    /// The field the player moves to in a rollout, None if it has no move
+    fn rollout_move(&mut self, state: &mut State, player: PlayerId) -> Option<Position> {
+        let head = state.player_head(player)?.clone();
+        match self.policy {
+            RolloutPolicy::Model if player != state.my_id => state
+                .predict_moves(player)
+                .choose_weighted(self.rng, |(_p, probability)| *probability)
+                .ok()
+                .map(|(p, _probability)| p.clone()),
+            RolloutPolicy::Random | RolloutPolicy::Model => {
+                let targets: Vec<Position> = iter_directions()
+                    .map(|d| move_by_direction(&head, d, &state.game_size))
+                    .filter(|p| !state.is_occupied(p.clone()))
+                    .collect();
+                targets.choose(self.rng).cloned()
+            }
+            RolloutPolicy::Strategy(strategy) => {
+                let me = state.switch_player(player);
+                let decision = strategy.decide(state, self.rng, self.deadline);
+                state.switch_player(me);
+                decision
+                    .direction
+                    .map(|d| move_by_direction(&head, &d, &state.game_size))
+            }
+        }
+    }
+}
+
+/// Apply the moves of all players simultaneously, marking the players which died in this round
+/// and removing them from the board like the server does.
+fn play_round(
+    state: &mut State,
+    players: &[PlayerId],
+    moves: &[Option<Position>],
+    died: &mut [Option<u32>],
+    round: u32,
+    undos: &mut Vec<Undo>,
+) {
+    resolve_collisions(moves, died, round);
+    undos.push(
+        state.make_moves(
+            &players
+                .iter()
+                .zip(moves.iter())
+                .filter_map(|(player, target)| target.clone().map(|t| (*player, t)))
+                .collect::<Vec<_>>(),
+        ),
+    );
+    let dead: Vec<PlayerId> = players
+        .iter()
+        .zip(died.iter())
+        .filter(|(_player, died)| **died == Some(round))
+        .map(|(player, _died)| *player)
+        .collect();
+    undos.push(state.remove_players(&dead));
+}
+
+/// Whether we died or all opponents died
+fn is_finished(died: &[Option<u32>]) -> bool {
+    died[0].is_some() || died[1..].iter().all(|d| d.is_some())
+}
+
+/// Reward of each player at the end of a playout between 0 and 1. Surviving players share the
+/// upper half by the size of their territories, so the last survivor gets 1. Players which died
+/// get less than half, the less the earlier they died. Dead players are no longer on the board,
+/// so they don't claim any territory.
+fn rewards(
+    state: &State,
+    players: &[PlayerId],
+    died: &[Option<u32>],
+    rounds: u32,
+    deadline: &Deadline,
+) -> Vec<f32> {
+    let partition = voronoi(state, deadline);
+    let total: usize = players
+        .iter()
+        .zip(died.iter())
+        .filter(|(_player, died)| died.is_none())
+        .map(|(player, _died)| partition.territory_size(*player))
+        .sum();
+    players
+        .iter()
+        .zip(died.iter())
+        .map(|(player, died)| match died {
+            Some(round) => 0.5 * *round as f32 / rounds as f32,
+            None if total == 0 => 0.5,
+            None => 0.5 + 0.5 * partition.territory_size(*player) as f32 / total as f32,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::scenario::{parse, player};
+
+    fn mcts_config(rollout_policy: &str) -> AlgorithmConfig {
+        AlgorithmConfig {
+            algorithm: "mcts".to_owned(),
+            mcts: MctsConfig {
+                rollout_policy: rollout_policy.to_owned(),
+                ..MctsConfig::default()
+            },
+            ..AlgorithmConfig::default()
+        }
+    }
+
+    #[test]
+    fn accepts_strategies_as_rollout_policy() {
+        for policy in ["random", "model", "ranked", "voronoi"].iter() {
+            assert!(create_strategy(&mcts_config(policy)).is_ok(), "{}", policy);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_rollout_policy() {
+        for policy in ["mcts", "bogus"].iter() {
+            match create_strategy(&mcts_config(policy)) {
+                Err(UnknownStrategy::RolloutPolicy(name)) => assert_eq!(name, *policy),
+                _ => panic!("Rollout policy {} was accepted", policy),
+            }
+        }
+    }
+
+    #[test]
+    fn stops_before_the_tick_deadline() {
+        let mut state = parse(
+            "
+            ..........
+            .@......A.
+            ..........
+            ",
+        );
+        let mut config = mcts_config("random");
+        config.mcts.iterations = u32::MAX;
+        let mut strategy = Mcts::new(&config).unwrap();
+        let deadline = Deadline::after(Duration::from_millis(200));
+        let decision = strategy.decide(&mut state, &mut StdRng::seed_from_u64(0), &deadline);
+        assert!(!deadline.expired());
+        assert!(decision.direction.is_some());
+    }
+
+    #[test]
+    fn removes_dead_players_from_the_board() {
+        let mut state = parse(
+            "
+            .......
+            @...B.C
+            ....b.c
+            ",
+        );
+        let players = vec![state.my_id(), player('b'), player('c')];
+        let mut died = vec![None; players.len()];
+        let mut undos = Vec::new();
+        // B and C collide on the field between them
+        let moves = vec![
+            Some(Position { x: 1, y: 1 }),
+            Some(Position { x: 5, y: 1 }),
+            Some(Position { x: 5, y: 1 }),
+        ];
+        play_round(&mut state, &players, &moves, &mut died, 1, &mut undos);
+        assert_eq!(died, vec![None, Some(1), Some(1)]);
+        assert_eq!(state.player_head(player('b')), None);
+        assert_eq!(state.field_occupation.iter().flatten().count(), 2);
+
+        let partition = voronoi(&state, &Deadline::none());
+        assert_eq!(
+            partition.territories.keys().collect::<Vec<_>>(),
+            vec![&state.my_id()]
+        );
+        let rewards = rewards(&state, &players, &died, 2, &Deadline::none());
+        assert_eq!(rewards, vec![1.0, 0.25, 0.25]);
+
+        for undo in undos.into_iter().rev() {
+            state.unmake_moves(undo);
+        }
+        assert_eq!(
+            state.player_head(player('b')),
+            Some(&Position { x: 4, y: 1 })
+        );
+        assert_eq!(state.field_occupation.iter().flatten().count(), 5);
+    }
+}
@@ -0,0 +1,424 @@
+//! Sets of fields packed into bits, for fast flood fills on the torus shaped board.
+//!
+//! Every row of the board is stored in `ceil(width / 64)` consecutive words, with bit `x % 64` of
+//! word `x / 64` representing the field in column `x`. Neighbourhoods are computed for all fields
+//! at once by shifting whole rows, wrapping around at the borders of the board.
+
+use super::Deadline;
+use crate::Position;
+use std::ops::{BitAndAssign, BitOrAssign};
+
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Bitboard {
+    width: u32,
+    height: u32,
+    words_per_row: usize,
+    words: Vec<u64>,
+}
+
+impl Bitboard {
+    /// An empty set of fields for a board of the given size
+    pub fn new(size: &Position) -> Self {
+        let words_per_row = (size.x as usize).div_ceil(64);
+        Bitboard {
+            width: size.x,
+            height: size.y,
+            words_per_row,
+            words: vec![0; words_per_row * size.y as usize],
+        }
+    }
+
+    pub fn from_positions<'a>(
+        size: &Position,
+        positions: impl IntoIterator<Item = &'a Position>,
+    ) -> Self {
+        let mut result = Bitboard::new(size);
+        for p in positions {
+            result.set(p);
+        }
+        result
+    }
+
+    fn index(&self, p: &Position) -> (usize, u64) {
+        (
+            p.y as usize * self.words_per_row + p.x as usize / 64,
+            1 << (p.x % 64),
+        )
+    }
+
+    pub fn get(&self, p: &Position) -> bool {
+        let (i, bit) = self.index(p);
+        self.words[i] & bit != 0
+    }
+
+    pub fn set(&mut self, p: &Position) {
+        let (i, bit) = self.index(p);
+        self.words[i] |= bit;
+    }
+
+    pub fn unset(&mut self, p: &Position) {
+        let (i, bit) = self.index(p);
+        self.words[i] &= !bit;
+    }
+
+    pub fn count(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Number of fields contained in both sets
+    pub fn count_common(&self, other: &Bitboard) -> usize {
+        self.words
+            .iter()
+            .zip(other.words.iter())
+            .map(|(a, b)| (a & b).count_ones() as usize)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|w| *w == 0)
+    }
+
+    pub fn intersects(&self, other: &Bitboard) -> bool {
+        self.words
+            .iter()
+            .zip(other.words.iter())
+            .any(|(a, b)| a & b != 0)
+    }
+
+    /// Remove all fields contained in `other`.
+    pub fn remove(&mut self, other: &Bitboard) {
+        for (a, b) in self.words.iter_mut().zip(other.words.iter()) {
+            *a &= !b;
+        }
+    }
+
+    /// All fields of the board which are not contained in this set
+    pub fn inverted(&self) -> Bitboard {
+        let mut result = self.clone();
+        for w in result.words.iter_mut() {
+            *w = !*w;
+        }
+        result.clear_padding();
+        result
+    }
+
+    /// Positions of all contained fields, row by row
+    pub fn iter(&self) -> impl Iterator<Item = Position> + '_ {
+        let words_per_row = self.words_per_row;
+        self.words.iter().enumerate().flat_map(move |(i, word)| {
+            let mut word = *word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros();
+                word &= word - 1;
+                Some(Position {
+                    x: (i % words_per_row) as u32 * 64 + bit,
+                    y: (i / words_per_row) as u32,
+                })
+            })
+        })
+    }
+
+    /// The bits beyond the last column of each row must always be zero.
+    fn clear_padding(&mut self) {
+        let used_bits = self.width % 64;
+        if used_bits == 0 {
+            return;
+        }
+        let mask = (1u64 << used_bits) - 1;
+        for row in self.words.chunks_mut(self.words_per_row) {
+            *row.last_mut().unwrap() &= mask;
+        }
+    }
+
+    /// All fields next to at least one of the contained fields, wrapping around at the borders
+    pub fn neighbours(&self) -> Bitboard {
+        let w = self.words_per_row;
+        let height = self.height as usize;
+        let last_column = self.width as usize - 1;
+        let mut result = Bitboard::new(&Position {
+            x: self.width,
+            y: self.height,
+        });
+        for y in 0..height {
+            let row = &self.words[y * w..(y + 1) * w];
+            let up = (y + height - 1) % height;
+            let down = (y + 1) % height;
+            for (i, word) in row.iter().enumerate() {
+                result.words[up * w + i] |= word;
+                result.words[down * w + i] |= word;
+            }
+
+            let out = &mut result.words[y * w..(y + 1) * w];
+            for i in 0..w {
+                // Right neighbours, carrying the highest bit into the next word
+                out[i] |= row[i] << 1;
+                if i > 0 {
+                    out[i] |= row[i - 1] >> 63;
+                }
+                // Left neighbours, carrying the lowest bit into the previous word
+                out[i] |= row[i] >> 1;
+                if i + 1 < w {
+                    out[i] |= row[i + 1] << 63;
+                }
+            }
+            if row[last_column / 64] & (1 << (last_column % 64)) != 0 {
+                out[0] |= 1;
+            }
+            if row[0] & 1 != 0 {
+                out[last_column / 64] |= 1 << (last_column % 64);
+            }
+        }
+        result.clear_padding();
+        result
+    }
+
+    /// All fields of this set which are connected to one of the `seeds` through fields of this set
+    pub fn flood_fill(&self, seeds: &Bitboard) -> Bitboard {
+        self.flood_fill_until(seeds, &Deadline::none())
+    }
+
+    /// Like [Bitboard::flood_fill], but returns the part of the region found so far when the
+    /// deadline expires.
+    pub fn flood_fill_until(&self, seeds: &Bitboard, deadline: &Deadline) -> Bitboard {
+        let mut region = seeds.clone();
+        region &= self;
+        let mut frontier = region.clone();
+        while !frontier.is_empty() && !deadline.expired() {
+            frontier = frontier.neighbours();
+            frontier &= self;
+            frontier.remove(&region);
+            region |= &frontier;
+        }
+        region
+    }
+
+    /// Breadth-first search from the `seeds`, yielding the fields at distance 0, 1, 2, … Like in a
+    /// search with a queue, fields outside `expandable` are reached but not expanded.
+    pub fn layers<'a>(seeds: &Bitboard, expandable: &'a Bitboard) -> Layers<'a> {
+        Layers {
+            expandable,
+            visited: seeds.clone(),
+            frontier: seeds.clone(),
+        }
+    }
+}
+
+impl BitOrAssign<&Bitboard> for Bitboard {
+    fn bitor_assign(&mut self, rhs: &Bitboard) {
+        for (a, b) in self.words.iter_mut().zip(rhs.words.iter()) {
+            *a |= b;
+        }
+    }
+}
+
+impl BitAndAssign<&Bitboard> for Bitboard {
+    fn bitand_assign(&mut self, rhs: &Bitboard) {
+        for (a, b) in self.words.iter_mut().zip(rhs.words.iter()) {
+            *a &= b;
+        }
+    }
+}
+
+/// Iterator over the distance layers of a breadth-first search, see [Bitboard::layers]
+pub struct Layers<'a> {
+    expandable: &'a Bitboard,
+    visited: Bitboard,
+    frontier: Bitboard,
+}
+
+impl Iterator for Layers<'_> {
+    type Item = Bitboard;
+
+    fn next(&mut self) -> Option<Bitboard> {
+        if self.frontier.is_empty() {
+            return None;
+        }
+        let mut next = self.frontier.clone();
+        next &= self.expandable;
+        let mut next = next.neighbours();
+        next.remove(&self.visited);
+        self.visited |= &next;
+        Some(std::mem::replace(&mut self.frontier, next))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::helper::{iter_directions, move_by_direction};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::{HashSet, VecDeque};
+
+    /// Odd sizes, sizes around the word boundaries and degenerate boards
+    const SIZES: [(u32, u32); 9] = [
+        (1, 1),
+        (1, 4),
+        (7, 1),
+        (5, 3),
+        (63, 5),
+        (64, 3),
+        (65, 7),
+        (127, 2),
+        (130, 5),
+    ];
+
+    fn sizes() -> impl Iterator<Item = Position> {
+        SIZES.iter().map(|(x, y)| Position { x: *x, y: *y })
+    }
+
+    fn all_positions(size: &Position) -> impl Iterator<Item = Position> {
+        let (width, height) = (size.x, size.y);
+        (0..height).flat_map(move |y| (0..width).map(move |x| Position { x, y }))
+    }
+
+    fn random_fields(size: &Position, density: f64, rng: &mut StdRng) -> HashSet<Position> {
+        all_positions(size)
+            .filter(|_p| rng.gen_bool(density))
+            .collect()
+    }
+
+    fn to_set(bitboard: &Bitboard) -> HashSet<Position> {
+        bitboard.iter().collect()
+    }
+
+    /// Breadth-first search with a queue, like the strategies did before bitboards. Fields
+    /// outside `expandable` are reached but not expanded.
+    fn reference_layers(
+        size: &Position,
+        seeds: &HashSet<Position>,
+        expandable: &HashSet<Position>,
+    ) -> Vec<HashSet<Position>> {
+        let mut layers: Vec<HashSet<Position>> = Vec::new();
+        let mut visited = seeds.clone();
+        let mut queue: VecDeque<(usize, Position)> = seeds.iter().map(|p| (0, p.clone())).collect();
+        while let Some((dist, p)) = queue.pop_front() {
+            if layers.len() <= dist {
+                layers.push(HashSet::new());
+            }
+            layers[dist].insert(p.clone());
+            if expandable.contains(&p) {
+                for d in iter_directions() {
+                    let next = move_by_direction(&p, d, size);
+                    if visited.insert(next.clone()) {
+                        queue.push_back((dist + 1, next));
+                    }
+                }
+            }
+        }
+        layers
+    }
+
+    fn padding_is_clear(bitboard: &Bitboard) -> bool {
+        let used_bits = bitboard.width % 64;
+        used_bits == 0
+            || bitboard
+                .words
+                .chunks(bitboard.words_per_row)
+                .all(|row| row.last().unwrap() >> used_bits == 0)
+    }
+
+    #[test]
+    fn neighbours_wrap_around() {
+        let size = Position { x: 65, y: 3 };
+        let corner = Bitboard::from_positions(&size, [&Position { x: 0, y: 0 }]);
+        let expected: HashSet<Position> = [(64, 0), (1, 0), (0, 2), (0, 1)]
+            .iter()
+            .map(|(x, y)| Position { x: *x, y: *y })
+            .collect();
+        assert_eq!(to_set(&corner.neighbours()), expected);
+
+        let last = Bitboard::from_positions(&size, [&Position { x: 64, y: 2 }]);
+        let expected: HashSet<Position> = [(63, 2), (0, 2), (64, 1), (64, 0)]
+            .iter()
+            .map(|(x, y)| Position { x: *x, y: *y })
+            .collect();
+        assert_eq!(to_set(&last.neighbours()), expected);
+    }
+
+    #[test]
+    fn neighbours_match_single_moves() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for size in sizes() {
+            for _ in 0..10 {
+                let fields = random_fields(&size, 0.2, &mut rng);
+                let bitboard = Bitboard::from_positions(&size, fields.iter());
+                let expected: HashSet<Position> = fields
+                    .iter()
+                    .flat_map(|p| iter_directions().map(move |d| (p, d)))
+                    .map(|(p, d)| move_by_direction(p, d, &size))
+                    .collect();
+                let neighbours = bitboard.neighbours();
+                assert_eq!(to_set(&neighbours), expected, "{:?}", size);
+                assert!(padding_is_clear(&neighbours), "{:?}", size);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_padding_clear() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for size in sizes() {
+            let fields = random_fields(&size, 0.5, &mut rng);
+            let bitboard = Bitboard::from_positions(&size, fields.iter());
+            let inverted = bitboard.inverted();
+            assert!(padding_is_clear(&inverted), "{:?}", size);
+            assert_eq!(
+                inverted.count() + bitboard.count(),
+                (size.x * size.y) as usize
+            );
+            assert!(!inverted.intersects(&bitboard));
+            let full = Bitboard::new(&size).inverted();
+            assert!(padding_is_clear(&full.neighbours()), "{:?}", size);
+            assert_eq!(full.neighbours(), full, "{:?}", size);
+        }
+    }
+
+    #[test]
+    fn flood_fill_matches_queue_search() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for size in sizes() {
+            for _ in 0..10 {
+                let free = random_fields(&size, 0.6, &mut rng);
+                let seeds = random_fields(&size, 0.05, &mut rng);
+                let mut expected: HashSet<Position> = reference_layers(&size, &seeds, &free)
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                expected.retain(|p| free.contains(p));
+
+                let free_board = Bitboard::from_positions(&size, free.iter());
+                let seed_board = Bitboard::from_positions(&size, seeds.iter());
+                let region = free_board.flood_fill(&seed_board);
+                assert_eq!(to_set(&region), expected, "{:?}", size);
+                assert!(padding_is_clear(&region), "{:?}", size);
+            }
+        }
+    }
+
+    #[test]
+    fn layers_match_queue_search() {
+        let mut rng = StdRng::seed_from_u64(4);
+        for size in sizes() {
+            for _ in 0..10 {
+                let free = random_fields(&size, 0.6, &mut rng);
+                let start = Position {
+                    x: rng.gen_range(0..size.x),
+                    y: rng.gen_range(0..size.y),
+                };
+                let seeds: HashSet<Position> = [start].iter().cloned().collect();
+                let expected = reference_layers(&size, &seeds, &free);
+
+                let free_board = Bitboard::from_positions(&size, free.iter());
+                let seed_board = Bitboard::from_positions(&size, seeds.iter());
+                let layers: Vec<HashSet<Position>> = Bitboard::layers(&seed_board, &free_board)
+                    .map(|layer| to_set(&layer))
+                    .collect();
+                assert_eq!(layers, expected, "{:?}", size);
+            }
+        }
+    }
+}
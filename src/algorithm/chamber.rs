@@ -0,0 +1,403 @@
+//! Analysis of the shape of an empty region for filling it with as few wasted fields as possible.
+//!
+//! Fields whose removal would split the region (articulation points) act as doors between
+//! chambers. Once we pass a door, we can't come back, so the fields we can fill are bounded by the
+//! best path through the tree of chambers. Within a chamber, every step alternates between the
+//! two colours of a checkerboard, which bounds the fillable fields further.
+
+use super::bitboard::Bitboard;
+use super::helper::{iter_directions, move_by_direction};
+use super::{Deadline, State};
+use crate::Position;
+use std::collections::VecDeque;
+
+pub struct Chamber {
+    /// Estimate of the fields we can fill in this chamber when entering it
+    pub fillable: usize,
+    /// The chamber we come from, None for the chamber containing the start field
+    pub parent: Option<usize>,
+    /// Estimate of the fields we can fill in this chamber and the best chambers behind it
+    pub best_path: usize,
+}
+
+pub struct ChamberTree {
+    pub chambers: Vec<Chamber>,
+    /// Index of the chamber containing the start field
+    pub root: usize,
+}
+
+impl ChamberTree {
+    /// Estimate of the number of fields we can fill from the start field
+    pub fn fillable_fields(&self) -> usize {
+        self.chambers[self.root].best_path
+    }
+}
+
+/// Colour of the field on a checkerboard
+fn colour(p: &Position) -> usize {
+    ((p.x + p.y) % 2) as usize
+}
+
+/// Neighbours of `p` within `region`, without duplicates on tiny boards
+fn neighbours_in(region: &Bitboard, p: &Position, game_size: &Position) -> Vec<Position> {
+    let mut result = Vec::with_capacity(4);
+    for d in iter_directions() {
+        let n = move_by_direction(p, d, game_size);
+        if n != *p && region.get(&n) && !result.contains(&n) {
+            result.push(n);
+        }
+    }
+    result
+}
+
+/// Articulation points of the graph of fields in `region`, which has to be connected and contain
+/// `start`. Uses an iterative depth-first search to cope with long corridors.
+pub fn articulation_points(region: &Bitboard, start: &Position, game_size: &Position) -> Bitboard {
+    let mut discovery = ndarray::Array2::<u32>::zeros(game_size.as_dim());
+    let mut low = ndarray::Array2::<u32>::zeros(game_size.as_dim());
+    let mut result = Bitboard::new(game_size);
+    let mut counter = 1;
+    let mut root_children = 0;
+
+    discovery[start.as_dim()] = counter;
+    low[start.as_dim()] = counter;
+    // Field, its parent in the search tree, its neighbours and the next neighbour to visit
+    let mut stack = vec![(
+        start.clone(),
+        None,
+        neighbours_in(region, start, game_size),
+        0,
+    )];
+    while let Some((field, parent, neighbours, next)) = stack.last_mut() {
+        if *next < neighbours.len() {
+            let n = neighbours[*next].clone();
+            *next += 1;
+            if Some(&n) == parent.as_ref() {
+                continue;
+            }
+            if discovery[n.as_dim()] == 0 {
+                counter += 1;
+                discovery[n.as_dim()] = counter;
+                low[n.as_dim()] = counter;
+                let field = field.clone();
+                if stack.len() == 1 {
+                    root_children += 1;
+                }
+                let neighbours = neighbours_in(region, &n, game_size);
+                stack.push((n, Some(field), neighbours, 0));
+            } else {
+                low[field.as_dim()] = low[field.as_dim()].min(discovery[n.as_dim()]);
+            }
+        } else {
+            let (field, parent, _neighbours, _next) = stack.pop().unwrap();
+            if let Some(parent) = parent {
+                low[parent.as_dim()] = low[parent.as_dim()].min(low[field.as_dim()]);
+                // The root is handled separately below
+                if stack.len() > 1 && low[field.as_dim()] >= discovery[parent.as_dim()] {
+                    result.set(&parent);
+                }
+            }
+        }
+    }
+    if root_children > 1 {
+        result.set(start);
+    }
+    result
+}
+
+/// Upper bound for the length of a path through `counts` fields of each colour, starting on a
+/// field of colour `entry`
+fn parity_fillable(counts: [usize; 2], entry: usize) -> usize {
+    let (same, other) = (counts[entry], counts[1 - entry]);
+    if same > other {
+        2 * other + 1
+    } else {
+        2 * same
+    }
+}
+
+/// Build the chamber tree of the empty region around the free field `start`.
+pub fn analyse_chambers(state: &State, start: &Position) -> ChamberTree {
+    let game_size = &state.game_size;
+    let region = state
+        .free_fields()
+        .flood_fill(&Bitboard::from_positions(game_size, [start]));
+    let articulation_points = articulation_points(&region, start, game_size);
+    let mut inner = region.clone();
+    inner.remove(&articulation_points);
+    // The checkerboard colouring only works if the board wraps around at even sizes
+    let use_parity = game_size.x.is_multiple_of(2) && game_size.y.is_multiple_of(2);
+
+    // Label the chambers: every articulation point is a chamber of its own
+    let mut labels = ndarray::Array2::<Option<usize>>::from_elem(game_size.as_dim(), None);
+    let mut door_fields: Vec<Option<Position>> = Vec::new();
+    let mut colour_counts: Vec<[usize; 2]> = Vec::new();
+    for p in region.iter() {
+        if labels[p.as_dim()].is_some() {
+            continue;
+        }
+        let label = door_fields.len();
+        let mut counts = [0, 0];
+        if articulation_points.get(&p) {
+            labels[p.as_dim()] = Some(label);
+            counts[colour(&p)] += 1;
+            door_fields.push(Some(p));
+        } else {
+            for q in inner
+                .flood_fill(&Bitboard::from_positions(game_size, [&p]))
+                .iter()
+            {
+                labels[q.as_dim()] = Some(label);
+                counts[colour(&q)] += 1;
+            }
+            door_fields.push(None);
+        }
+        colour_counts.push(counts);
+    }
+
+    let mut adjacent: Vec<Vec<usize>> = vec![Vec::new(); door_fields.len()];
+    for door in articulation_points.iter() {
+        let a = labels[door.as_dim()].unwrap();
+        for n in neighbours_in(&region, &door, game_size) {
+            let b = labels[n.as_dim()].unwrap();
+            if a != b && !adjacent[a].contains(&b) {
+                adjacent[a].push(b);
+                adjacent[b].push(a);
+            }
+        }
+    }
+
+    // Breadth-first search through the chambers from the start, remembering the colour of the
+    // field through which each chamber is entered
+    let root = labels[start.as_dim()].unwrap();
+    let mut parents = vec![None; door_fields.len()];
+    let mut entry_colours = vec![colour(start); door_fields.len()];
+    let mut visited = vec![false; door_fields.len()];
+    let mut order = Vec::new();
+    let mut queue = VecDeque::from([root]);
+    visited[root] = true;
+    while let Some(chamber) = queue.pop_front() {
+        order.push(chamber);
+        for &next in adjacent[chamber].iter() {
+            if visited[next] {
+                continue;
+            }
+            visited[next] = true;
+            parents[next] = Some(chamber);
+            entry_colours[next] = match (&door_fields[chamber], &door_fields[next]) {
+                (_, Some(door)) => colour(door),
+                (Some(door), None) => 1 - colour(door),
+                (None, None) => unreachable!("Chambers are only adjacent through doors"),
+            };
+            queue.push_back(next);
+        }
+    }
+
+    let mut chambers: Vec<Chamber> = (0..door_fields.len())
+        .map(|i| {
+            let size = colour_counts[i][0] + colour_counts[i][1];
+            Chamber {
+                fillable: if use_parity {
+                    parity_fillable(colour_counts[i], entry_colours[i])
+                } else {
+                    size
+                },
+                parent: parents[i],
+                best_path: 0,
+            }
+        })
+        .collect();
+    for &i in order.iter().rev() {
+        let best_child = adjacent[i]
+            .iter()
+            .filter(|child| chambers[**child].parent == Some(i))
+            .map(|child| chambers[*child].best_path)
+            .max()
+            .unwrap_or(0);
+        chambers[i].best_path = chambers[i].fillable + best_child;
+    }
+
+    ChamberTree { chambers, root }
+}
+
+/// Whether no opponent can reach the empty region around our head anymore. Returns false if the
+/// deadline expires before the whole region is searched.
+pub fn is_sealed_off(state: &State, deadline: &Deadline) -> bool {
+    let head = Bitboard::from_positions(&state.game_size, [&state.my_position]);
+    let mut expandable = state.free_fields();
+    expandable |= &head;
+    let opponent_heads = state.head_fields(false);
+    for layer in Bitboard::layers(&head, &expandable) {
+        if layer.intersects(&opponent_heads) || deadline.expired() {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::scenario::parse;
+
+    /// The fields marked with `.` as region, everything else outside of it
+    fn region(board: &str) -> (Bitboard, Position) {
+        let rows: Vec<&str> = board
+            .lines()
+            .map(|row| row.trim())
+            .filter(|row| !row.is_empty())
+            .collect();
+        let size = Position {
+            x: rows[0].len() as u32,
+            y: rows.len() as u32,
+        };
+        let mut region = Bitboard::new(&size);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, field) in row.chars().enumerate() {
+                if field == '.' {
+                    region.set(&Position {
+                        x: x as u32,
+                        y: y as u32,
+                    });
+                }
+            }
+        }
+        (region, size)
+    }
+
+    fn cut_vertices(board: &str, start: (u32, u32)) -> Vec<(u32, u32)> {
+        let (region, size) = region(board);
+        let start = Position {
+            x: start.0,
+            y: start.1,
+        };
+        articulation_points(&region, &start, &size)
+            .iter()
+            .map(|p| (p.x, p.y))
+            .collect()
+    }
+
+    /// Two rooms connected by a corridor of one field
+    const ROOMS: &str = "
+        #######
+        #..#..#
+        #.....#
+        #..#..#
+        #######
+    ";
+
+    #[test]
+    fn finds_corridor_between_rooms() {
+        let expected = vec![(2, 2), (3, 2), (4, 2)];
+        assert_eq!(cut_vertices(ROOMS, (1, 1)), expected);
+        // Also when starting on one of the cut vertices
+        assert_eq!(cut_vertices(ROOMS, (3, 2)), expected);
+        assert_eq!(cut_vertices(ROOMS, (4, 2)), expected);
+    }
+
+    #[test]
+    fn finds_no_cut_vertex_in_a_ring() {
+        let ring = "
+            #####
+            #...#
+            #.#.#
+            #...#
+            #####
+        ";
+        assert!(cut_vertices(ring, (1, 1)).is_empty());
+        assert!(cut_vertices(ring, (2, 3)).is_empty());
+    }
+
+    #[test]
+    fn treats_every_inner_field_of_a_line_as_cut_vertex() {
+        let line = "
+            ######
+            #....#
+            ######
+        ";
+        assert_eq!(cut_vertices(line, (1, 1)), vec![(2, 1), (3, 1)]);
+        assert_eq!(cut_vertices(line, (3, 1)), vec![(2, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn follows_corridor_across_the_edge() {
+        // The rooms are only connected through the first and the last column
+        let board = "
+            #######
+            #..#..#
+            ...#...
+            #..#..#
+            #######
+        ";
+        let expected = vec![(0, 2), (1, 2), (5, 2), (6, 2)];
+        assert_eq!(cut_vertices(board, (2, 1)), expected);
+        assert_eq!(cut_vertices(board, (0, 2)), expected);
+    }
+
+    #[test]
+    fn wraps_around_a_ring_across_the_edge() {
+        // A row of free fields is a ring on the torus
+        let board = "
+            #####
+            .....
+            #####
+        ";
+        assert!(cut_vertices(board, (2, 1)).is_empty());
+    }
+
+    #[test]
+    fn parity_bounds_paths_by_the_rarer_colour() {
+        assert_eq!(parity_fillable([2, 2], 0), 4);
+        // Starting on the more frequent colour, the path can end on it
+        assert_eq!(parity_fillable([5, 4], 0), 9);
+        assert_eq!(parity_fillable([4, 5], 0), 8);
+        assert_eq!(parity_fillable([1, 3], 1), 3);
+    }
+
+    #[test]
+    fn parity_bound_is_smaller_than_the_room() {
+        // A room of 3x3 fields has five fields of one colour and four of the other
+        let state = parse(
+            "
+            ********
+            *...****
+            *...****
+            *...@***
+            ********
+            ********
+            ",
+        );
+        let from_rare_colour = analyse_chambers(&state, &Position { x: 2, y: 3 });
+        assert_eq!(from_rare_colour.chambers.len(), 1);
+        assert_eq!(from_rare_colour.fillable_fields(), 8);
+        let from_frequent_colour = analyse_chambers(&state, &Position { x: 3, y: 3 });
+        assert_eq!(from_frequent_colour.fillable_fields(), 9);
+    }
+
+    #[test]
+    fn best_path_takes_the_larger_side() {
+        // From the corridor, we can fill 4 more fields on the left and 7 on the right, where the
+        // corridor opens into a room
+        let state = parse(
+            "
+            *********
+            *.****..*
+            *.......@
+            *.****..*
+            *********
+            ",
+        );
+        let tree = analyse_chambers(&state, &Position { x: 4, y: 2 });
+        let root = &tree.chambers[tree.root];
+        assert_eq!(root.fillable, 1);
+        let mut children: Vec<usize> = tree
+            .chambers
+            .iter()
+            .filter(|chamber| chamber.parent == Some(tree.root))
+            .map(|chamber| chamber.best_path)
+            .collect();
+        children.sort();
+        assert_eq!(children, vec![4, 7]);
+        assert_eq!(tree.fillable_fields(), 8);
+    }
+}
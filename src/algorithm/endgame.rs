@@ -0,0 +1,154 @@
+//! Survival mode for the end of a game, when no opponent can reach us anymore.
+//!
+//! The only goal left is to survive as many ticks as possible, i.e. to find a long path through
+//! our region. We look a few steps ahead and rate the remaining region by its chamber structure,
+//! preferring fields next to walls to avoid cutting the region into pieces.
+
+use super::chamber::{analyse_chambers, is_sealed_off};
+use super::helper::{iter_directions, move_by_direction};
+use super::{CandidateScore, Deadline, Decision, State, Strategy};
+use log::{debug, info};
+use ordered_float::OrderedFloat;
+use rand::rngs::StdRng;
+
+/// Number of own steps simulated before rating the remaining region
+const LOOKAHEAD: u32 = 3;
+
+/// Wraps a strategy and takes over once we are sealed off from all opponents.
+pub struct Survival {
+    inner: Box<dyn Strategy>,
+}
+
+impl Survival {
+    pub fn new(inner: Box<dyn Strategy>) -> Self {
+        Survival { inner }
+    }
+}
+
+impl Strategy for Survival {
+    fn init(&mut self, state: &State) {
+        self.inner.init(state);
+    }
+
+    fn decide(&mut self, state: &mut State, rng: &mut StdRng, deadline: &Deadline) -> Decision {
+        if is_sealed_off(state, deadline) {
+            info!("Sealed off, filling our region.");
+            fill_region(state, deadline)
+        } else {
+            self.inner.decide(state, rng, deadline)
+        }
+    }
+
+    fn on_death(&mut self, state: &State) {
+        self.inner.on_death(state);
+    }
+
+    fn on_game_end(&mut self, state: &State, won: bool) {
+        self.inner.on_game_end(state, won);
+    }
+}
+
+/// Choose the move which lets us fill the most fields of our region.
+pub fn fill_region(state: &mut State, deadline: &Deadline) -> Decision {
+    let possible_directions = iter_directions()
+        .filter(|d| !state.is_occupied(move_by_direction(&state.my_position, d, &state.game_size)))
+        .collect::<Vec<_>>();
+    let mut directions = possible_directions
+        .into_iter()
+        .take_while(|_d| !deadline.expired())
+        .map(|d| {
+            let target = move_by_direction(&state.my_position, d, &state.game_size);
+            // Hugging walls wastes fewer fields than cutting through open space
+            let free_neighbours = iter_directions()
+                .map(|d| move_by_direction(&target, d, &state.game_size))
+                .filter(|p| *p != state.my_position && !state.is_occupied(p.clone()))
+                .count();
+            let undo = state.make_moves(&[(state.my_id, target)]);
+            let fillable = 1 + longest_path(state, LOOKAHEAD - 1, deadline);
+            state.unmake_moves(undo);
+            CandidateScore {
+                space_score: Some(-(fillable as f32)),
+                direction_score: Some(free_neighbours as f32),
+                ..CandidateScore::new(d.clone())
+            }
+        })
+        .collect::<Vec<_>>();
+    directions.sort_by_key(|c| {
+        (
+            OrderedFloat(c.space_score.unwrap()),
+            OrderedFloat(c.direction_score.unwrap()),
+        )
+    });
+    debug!("Directions: {:?}", directions);
+    Decision::from_candidates(directions)
+}
+
+/// Estimate of the number of fields we can still fill from our head, trying all paths of `depth`
+/// steps and rating the region at their ends by its chamber tree
+fn longest_path(state: &mut State, depth: u32, deadline: &Deadline) -> usize {
+    let targets = iter_directions()
+        .map(|d| move_by_direction(&state.my_position, d, &state.game_size))
+        .filter(|p| !state.is_occupied(p.clone()))
+        .collect::<Vec<_>>();
+    targets
+        .into_iter()
+        .map(|target| {
+            if depth == 0 || deadline.expired() {
+                analyse_chambers(state, &target).fillable_fields()
+            } else {
+                let undo = state.make_moves(&[(state.my_id, target)]);
+                let result = 1 + longest_path(state, depth - 1, deadline);
+                state.unmake_moves(undo);
+                result
+            }
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::scenario::parse;
+    use crate::MoveDirection;
+    use std::time::Duration;
+
+    /// Left leads into a pocket of two fields, right into a room of six. A can't reach us.
+    const SEALED_OFF: &str = "
+        aaaaaaa
+        a.a...a
+        a.@...a
+        aa*aaaA
+    ";
+
+    #[test]
+    fn detects_sealed_off_region() {
+        let state = parse(SEALED_OFF);
+        assert!(is_sealed_off(&state, &Deadline::none()));
+    }
+
+    #[test]
+    fn detects_opponent_across_the_edge() {
+        let state = parse(
+            "
+            aaaaaaa
+            A*@....
+            aa*aaaa
+            ",
+        );
+        assert!(!is_sealed_off(&state, &Deadline::none()));
+    }
+
+    #[test]
+    fn is_not_sealed_off_when_out_of_time() {
+        let state = parse(SEALED_OFF);
+        assert!(!is_sealed_off(&state, &Deadline::after(Duration::ZERO)));
+    }
+
+    #[test]
+    fn fills_the_larger_chamber() {
+        let mut state = parse(SEALED_OFF);
+        let decision = fill_region(&mut state, &Deadline::none());
+        assert_eq!(decision.direction, Some(MoveDirection::Right));
+    }
+}
@@ -0,0 +1,255 @@
+//! Rendering the board of a [State] as SVG or as palette image for PNG and GIF export.
+
+use super::helper::move_by_direction;
+use super::{CandidateScore, State};
+use crate::client::PlayerId;
+use crate::{MoveDirection, Position};
+use std::fmt::Write;
+
+/// Events of a tick drawn on top of the board
+#[derive(Default)]
+pub struct FrameAnnotation {
+    /// Players which died since the last tick, with the position of their last head
+    pub deaths: Vec<(PlayerId, Position)>,
+    /// Direction sent by our bot in this tick
+    pub chosen: Option<MoveDirection>,
+    /// Scores of the candidate directions of our algorithm, best first
+    pub scores: Vec<CandidateScore>,
+}
+
+/// An image with one palette index per pixel, see [palette]
+pub struct PaletteImage {
+    pub width: u16,
+    pub height: u16,
+    pub pixels: Vec<u8>,
+}
+
+const PLAYER_COLOURS: [(u8, u8, u8); 12] = [
+    (230, 25, 75),
+    (60, 180, 75),
+    (0, 130, 200),
+    (255, 225, 25),
+    (240, 50, 230),
+    (70, 240, 240),
+    (245, 130, 48),
+    (145, 30, 180),
+    (210, 245, 60),
+    (0, 0, 128),
+    (255, 215, 180),
+    (170, 110, 40),
+];
+
+const BACKGROUND: u8 = 0;
+const GRID: u8 = 1;
+const WHITE: u8 = 2;
+const DEATH: u8 = 3;
+const FIRST_PLAYER_COLOUR: u8 = 4;
+
+/// RGB palette of all images, containing fixed colours followed by a bright (head and own trail)
+/// and a dimmed (opponent trail) colour per player
+pub fn palette() -> Vec<u8> {
+    let mut palette = vec![24, 24, 24, 40, 40, 40, 255, 255, 255, 255, 0, 0];
+    for (r, g, b) in PLAYER_COLOURS {
+        palette.extend([r, g, b]);
+    }
+    for (r, g, b) in PLAYER_COLOURS {
+        palette.extend([r / 2, g / 2, b / 2]);
+    }
+    palette
+}
+
+fn player_colour(player: PlayerId, bright: bool) -> u8 {
+    let index = (player.0 as usize % PLAYER_COLOURS.len()) as u8;
+    if bright {
+        FIRST_PLAYER_COLOUR + index
+    } else {
+        FIRST_PLAYER_COLOUR + PLAYER_COLOURS.len() as u8 + index
+    }
+}
+
+fn hex_colour(index: u8) -> String {
+    let palette = palette();
+    let i = index as usize * 3;
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        palette[i],
+        palette[i + 1],
+        palette[i + 2]
+    )
+}
+
+/// Colour of a field, or None if it is empty
+fn field_colour(state: &State, p: &Position) -> Option<u8> {
+    state.field_occupation[p.as_dim()].map(|player| {
+        let is_head = state.player_heads.get(&player) == Some(p);
+        player_colour(player, is_head || player == state.my_id)
+    })
+}
+
+fn direction_offset(direction: &MoveDirection) -> (i32, i32) {
+    match direction {
+        MoveDirection::Up => (0, -1),
+        MoveDirection::Right => (1, 0),
+        MoveDirection::Down => (0, 1),
+        MoveDirection::Left => (-1, 0),
+    }
+}
+
+/// Render the board as SVG with `scale` pixels per field. The scores of the annotation are listed
+/// as text below the board.
+pub fn render_svg(state: &State, annotation: &FrameAnnotation, scale: u32) -> String {
+    let width = state.game_size.x * scale;
+    let board_height = state.game_size.y * scale;
+    let text_height = 16 * (annotation.scores.len() as u32 + 1);
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="monospace" font-size="12">"#,
+        width,
+        board_height + text_height
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="100%" height="100%" fill="{}"/>"#,
+        hex_colour(BACKGROUND)
+    );
+    for y in 0..state.game_size.y {
+        for x in 0..state.game_size.x {
+            let p = Position { x, y };
+            let colour = field_colour(state, &p).unwrap_or(GRID);
+            let is_head = state.player_heads.values().any(|head| *head == p);
+            let _ = writeln!(
+                svg,
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"{}/>"#,
+                x * scale + 1,
+                y * scale + 1,
+                scale - 2,
+                scale - 2,
+                hex_colour(colour),
+                if is_head {
+                    r#" stroke="white" stroke-width="2""#
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+    for (_player, position) in annotation.deaths.iter() {
+        let (x, y) = (position.x * scale, position.y * scale);
+        let _ = writeln!(
+            svg,
+            r#"<path d="M{} {} L{} {} M{} {} L{} {}" stroke="red" stroke-width="3"/>"#,
+            x,
+            y,
+            x + scale,
+            y + scale,
+            x + scale,
+            y,
+            x,
+            y + scale
+        );
+    }
+    if let Some(direction) = annotation.chosen.as_ref() {
+        let (dx, dy) = direction_offset(direction);
+        let cx = (state.my_position.x * scale + scale / 2) as i32;
+        let cy = (state.my_position.y * scale + scale / 2) as i32;
+        let _ = writeln!(
+            svg,
+            r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="white" stroke-width="3"/>"#,
+            cx,
+            cy,
+            cx + dx * scale as i32,
+            cy + dy * scale as i32
+        );
+    }
+    let chosen = annotation
+        .chosen
+        .as_ref()
+        .map(|d| format!("{:?}", d))
+        .unwrap_or_else(|| "-".to_owned());
+    let _ = writeln!(
+        svg,
+        r#"<text x="4" y="{}" fill="white">Chosen: {}</text>"#,
+        board_height + 13,
+        chosen
+    );
+    for (i, score) in annotation.scores.iter().enumerate() {
+        let _ = writeln!(
+            svg,
+            r#"<text x="4" y="{}" fill="white">{}</text>"#,
+            board_height + 13 + 16 * (i as u32 + 1),
+            score
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Render the board as palette image with `scale` pixels per field. Heads get a white border,
+/// deaths are marked with a red cross, and the chosen direction is drawn as white line from our
+/// head.
+pub fn render_pixels(state: &State, annotation: &FrameAnnotation, scale: u32) -> PaletteImage {
+    let width = state.game_size.x * scale;
+    let height = state.game_size.y * scale;
+    let mut pixels = vec![BACKGROUND; (width * height) as usize];
+    let mut set = |x: u32, y: u32, colour: u8| {
+        if x < width && y < height {
+            pixels[(y * width + x) as usize] = colour;
+        }
+    };
+
+    for y in 0..state.game_size.y {
+        for x in 0..state.game_size.x {
+            let p = Position { x, y };
+            let colour = field_colour(state, &p).unwrap_or(GRID);
+            let is_head = state.player_heads.values().any(|head| *head == p);
+            for py in 1..scale - 1 {
+                for px in 1..scale - 1 {
+                    let border = py == 1 || px == 1 || py == scale - 2 || px == scale - 2;
+                    set(
+                        x * scale + px,
+                        y * scale + py,
+                        if is_head && border { WHITE } else { colour },
+                    );
+                }
+            }
+        }
+    }
+    for (_player, position) in annotation.deaths.iter() {
+        for i in 0..scale {
+            set(position.x * scale + i, position.y * scale + i, DEATH);
+            set(
+                position.x * scale + scale - 1 - i,
+                position.y * scale + i,
+                DEATH,
+            );
+        }
+    }
+    if let Some(direction) = annotation.chosen.as_ref() {
+        let target = move_by_direction(&state.my_position, direction, &state.game_size);
+        let (dx, dy) = direction_offset(direction);
+        for i in 0..scale {
+            let (x, y) = if dx != 0 {
+                (i, scale / 2)
+            } else {
+                (scale / 2, i)
+            };
+            // Half the line in our head's field, half in the target field
+            if (dx + dy > 0) == (i >= scale / 2) {
+                set(
+                    state.my_position.x * scale + x,
+                    state.my_position.y * scale + y,
+                    WHITE,
+                );
+            } else {
+                set(target.x * scale + x, target.y * scale + y, WHITE);
+            }
+        }
+    }
+
+    PaletteImage {
+        width: width as u16,
+        height: height as u16,
+        pixels,
+    }
+}
@@ -1,6 +1,9 @@
-use crate::algorithm::State;
+use crate::algorithm::bitboard::Bitboard;
+use crate::algorithm::{Deadline, State};
+use crate::client::PlayerId;
 use crate::{MoveDirection, Position};
 use ordered_float::OrderedFloat;
+use std::collections::BTreeMap;
 
 pub fn iter_directions() -> impl Iterator<Item = &'static MoveDirection> {
     [
@@ -34,7 +37,7 @@ pub fn move_by_direction(pos: &Position, dir: &MoveDirection, game_size: &Positi
 }
 
 pub fn point_to_float_point_distance(p: &Position, x2: f32, y2: f32, game_size: &Position) -> f32 {
-    return [
+    [
         OrderedFloat(((p.x as f32 - x2).powi(2) + (p.y as f32 - y2).powi(2)).sqrt()),
         OrderedFloat(
             ((p.x as f32 - x2).powi(2) + ((p.y + game_size.y) as f32 - y2).powi(2)).sqrt(),
@@ -50,7 +53,7 @@ pub fn point_to_float_point_distance(p: &Position, x2: f32, y2: f32, game_size:
     .iter()
     .min()
     .unwrap()
-    .0;
+    .0
 }
 
 pub fn point_to_point_distance(p1: &Position, p2: &Position, game_size: &Position) -> f32 {
@@ -61,8 +64,7 @@ pub fn has_wall(pos: &Position, game_state: &State) -> bool {
     iter_directions()
         .map(|d| move_by_direction(pos, d, &game_state.game_size))
         .filter(|p| !game_state.player_heads.values().any(|head| *p == *head))
-        .map(|p| game_state.is_occupied(p))
-        .any(|b| b)
+        .any(|p| game_state.is_occupied(p))
 }
 
 pub fn has_neighbour_head(pos: &Position, game_state: &State) -> bool {
@@ -78,35 +80,280 @@ pub fn has_neighbour_head(pos: &Position, game_state: &State) -> bool {
 }
 
 pub fn distance_to_next_opponent_head(pos: &Position, game_state: &State) -> Option<u32> {
-    let mut visited = std::collections::HashSet::new();
-    let mut queue = std::collections::VecDeque::new();
+    let opponent_heads = game_state.head_fields(false);
+    let free = game_state.free_fields();
+    Bitboard::layers(
+        &Bitboard::from_positions(&game_state.game_size, [pos]),
+        &free,
+    )
+    .position(|layer| layer.intersects(&opponent_heads))
+    .map(|dist| dist as u32)
+}
+
+/// Partition of the empty fields by the player whose head reaches them first
+pub struct Voronoi {
+    pub territories: BTreeMap<PlayerId, Bitboard>,
+    /// Fields reached by several players at the same distance
+    pub contested: Bitboard,
+}
+
+impl Voronoi {
+    pub fn territory_size(&self, player: PlayerId) -> usize {
+        self.territories.get(&player).map_or(0, |t| t.count())
+    }
+}
+
+/// Breadth-first search from all player heads at once through the empty fields. Contested fields
+/// are not expanded further. The search stops early when the deadline expires.
+pub fn voronoi(game_state: &State, deadline: &Deadline) -> Voronoi {
+    let free = game_state.free_fields();
+    let mut claimed = Bitboard::new(&game_state.game_size);
+    let mut contested = Bitboard::new(&game_state.game_size);
+    let mut frontiers: Vec<(PlayerId, Bitboard)> = game_state
+        .player_heads
+        .iter()
+        .map(|(player, head)| {
+            (
+                *player,
+                Bitboard::from_positions(&game_state.game_size, [head]),
+            )
+        })
+        .collect();
+    let mut territories: BTreeMap<PlayerId, Bitboard> = frontiers
+        .iter()
+        .map(|(player, _frontier)| (*player, Bitboard::new(&game_state.game_size)))
+        .collect();
+
+    while frontiers
+        .iter()
+        .any(|(_player, frontier)| !frontier.is_empty())
+        && !deadline.expired()
+    {
+        let mut reached = Bitboard::new(&game_state.game_size);
+        let mut reached_twice = Bitboard::new(&game_state.game_size);
+        for (_player, frontier) in frontiers.iter_mut() {
+            let mut next = frontier.neighbours();
+            next &= &free;
+            next.remove(&claimed);
+            let mut both = next.clone();
+            both &= &reached;
+            reached_twice |= &both;
+            reached |= &next;
+            *frontier = next;
+        }
+        for (player, frontier) in frontiers.iter_mut() {
+            frontier.remove(&reached_twice);
+            *territories.get_mut(player).unwrap() |= frontier;
+        }
+        claimed |= &reached;
+        contested |= &reached_twice;
+    }
+    Voronoi {
+        territories,
+        contested,
+    }
+}
 
-    visited.insert(pos.clone());
-    queue.push_back((0usize, pos.clone()));
+/// Probability per field that at least one opponent moves onto it in this tick
+pub struct ContestedFields {
+    probabilities: ndarray::Array2<f32>,
+}
 
-    while let Some((dist, p)) = queue.pop_front() {
-        if game_state
-            .player_heads
+impl ContestedFields {
+    /// Since players moving onto the same field both die, this is also the probability of dying
+    /// when moving onto the field.
+    pub fn probability(&self, p: &Position) -> f32 {
+        self.probabilities[p.as_dim()]
+    }
+}
+
+/// Combine the predicted moves of all opponents into the probability of each field being taken,
+/// assuming the opponents decide independently.
+pub fn contested_fields(game_state: &State) -> ContestedFields {
+    let mut untouched = ndarray::Array2::<f32>::ones(game_state.game_size.as_dim());
+    for player in game_state.player_heads.keys() {
+        if *player == game_state.my_id {
+            continue;
+        }
+        for (target, probability) in game_state.predict_moves(*player) {
+            untouched[target.as_dim()] *= 1.0 - probability;
+        }
+    }
+    ContestedFields {
+        probabilities: untouched.mapv(|p| 1.0 - p),
+    }
+}
+
+/// All empty fields reachable from any of the given start positions.
+pub fn reachable_fields<'a>(
+    starts: impl IntoIterator<Item = &'a Position>,
+    game_state: &State,
+) -> Bitboard {
+    game_state
+        .free_fields()
+        .flood_fill(&Bitboard::from_positions(&game_state.game_size, starts))
+}
+
+/// Apply the server's rules for simultaneous moves: players without a move and players moving
+/// onto the same field as another player die in `round`. Players which already died are skipped.
+pub fn resolve_collisions(moves: &[Option<Position>], died: &mut [Option<u32>], round: u32) {
+    for (i, target) in moves.iter().enumerate() {
+        if died[i].is_some() {
+            continue;
+        }
+        let collision = moves
             .iter()
-            .filter(|(player, _head)| **player != game_state.my_id)
-            .any(|(_player, head)| *head == p)
-        {
-            return Some(dist as u32);
+            .enumerate()
+            .any(|(j, other)| i != j && target.is_some() && other == target);
+        if target.is_none() || collision {
+            died[i] = Some(round);
         }
-        if !game_state.is_occupied(p.clone()) {
-            for direction in [
-                MoveDirection::Up,
-                MoveDirection::Down,
-                MoveDirection::Left,
-                MoveDirection::Right,
-            ] {
-                let next_pos = move_by_direction(&p, &direction, &game_state.game_size);
-                if !visited.contains(&next_pos) {
-                    visited.insert(next_pos.clone());
-                    queue.push_back((dist + 1, next_pos));
-                }
-            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::scenario::{parse, player};
+
+    fn positions(fields: &[(u32, u32)]) -> Vec<Position> {
+        fields
+            .iter()
+            .map(|(x, y)| Position { x: *x, y: *y })
+            .collect()
+    }
+
+    fn fields(bitboard: &Bitboard) -> Vec<Position> {
+        bitboard.iter().collect()
+    }
+
+    #[test]
+    fn voronoi_splits_equidistant_fields() {
+        let state = parse(
+            "
+            aaaaaaa
+            @.....A
+            aaaaaaa
+            ",
+        );
+        let partition = voronoi(&state, &Deadline::none());
+        let us = state.my_id();
+        assert_eq!(
+            fields(&partition.territories[&us]),
+            positions(&[(1, 1), (2, 1)])
+        );
+        assert_eq!(
+            fields(&partition.territories[&player('a')]),
+            positions(&[(4, 1), (5, 1)])
+        );
+        assert_eq!(fields(&partition.contested), positions(&[(3, 1)]));
+        assert_eq!(partition.territory_size(us), 2);
+        assert_eq!(partition.territory_size(player('b')), 0);
+    }
+
+    #[test]
+    fn voronoi_wraps_around() {
+        let state = parse(
+            "
+            aaaaaaa
+            .@aA...
+            aaaaaaa
+            ",
+        );
+        let partition = voronoi(&state, &Deadline::none());
+        assert_eq!(
+            fields(&partition.territories[&state.my_id()]),
+            positions(&[(0, 1), (6, 1)])
+        );
+        assert_eq!(
+            fields(&partition.territories[&player('a')]),
+            positions(&[(4, 1), (5, 1)])
+        );
+        assert!(partition.contested.is_empty());
+    }
+
+    #[test]
+    fn voronoi_does_not_expand_contested_fields() {
+        // The room below is only reachable through the contested field between the heads
+        let state = parse(
+            "
+            aaaaaaa
+            a@.Aaaa
+            aa.aaaa
+            aa.aaaa
+            ",
+        );
+        let partition = voronoi(&state, &Deadline::none());
+        assert_eq!(fields(&partition.contested), positions(&[(2, 1)]));
+        assert_eq!(partition.territory_size(state.my_id()), 0);
+        assert_eq!(partition.territory_size(player('a')), 0);
+    }
+
+    #[test]
+    fn contested_fields_of_a_forced_move() {
+        let state = parse(
+            "
+            aaaaaa
+            @..Aaa
+            aaaaaa
+            ",
+        );
+        let contested = contested_fields(&state);
+        assert_eq!(contested.probability(&Position { x: 2, y: 1 }), 1.0);
+        // Our own moves don't count
+        assert_eq!(contested.probability(&Position { x: 1, y: 1 }), 0.0);
+    }
+
+    #[test]
+    fn contested_fields_combine_opponents_independently() {
+        let state = parse(
+            "
+            aaaaaaa
+            a.A.B.a
+            aaaaaaa
+            @aaaaaa
+            ",
+        );
+        let contested = contested_fields(&state);
+        // Without observed moves, only the chaser prefers a move: towards the other opponent,
+        // so both move onto the field between them with 0.6125 and away with 0.3875
+        let shared = 1.0 - 0.3875 * 0.3875;
+        let expected = [0.0, 0.3875, 0.0, shared, 0.0, 0.3875, 0.0];
+        for (x, expected) in expected.iter().enumerate() {
+            let field = Position { x: x as u32, y: 1 };
+            assert!((contested.probability(&field) - expected).abs() < 1e-6);
         }
+        assert_eq!(contested.probability(&Position { x: 0, y: 3 }), 0.0);
+    }
+
+    #[test]
+    fn resolve_collisions_kills_players_on_the_same_field() {
+        let moves: Vec<Option<Position>> = positions(&[(1, 1), (1, 1), (2, 1), (1, 1)])
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut died = vec![None; 4];
+        resolve_collisions(&moves, &mut died, 3);
+        assert_eq!(died, vec![Some(3), Some(3), None, Some(3)]);
+    }
+
+    #[test]
+    fn resolve_collisions_kills_players_without_move() {
+        let moves = vec![Some(Position { x: 1, y: 1 }), None, None];
+        let mut died = vec![None; 3];
+        resolve_collisions(&moves, &mut died, 1);
+        assert_eq!(died, vec![None, Some(1), Some(1)]);
+    }
+
+    #[test]
+    fn resolve_collisions_keeps_earlier_deaths() {
+        let moves = vec![
+            Some(Position { x: 1, y: 1 }),
+            None,
+            Some(Position { x: 2, y: 1 }),
+        ];
+        let mut died = vec![None, Some(1), None];
+        resolve_collisions(&moves, &mut died, 2);
+        assert_eq!(died, vec![None, Some(1), None]);
     }
-    return None;
 }
@@ -0,0 +1,352 @@
+//! Models of the opponents' behaviour, fitted to their moves observed during a game.
+//!
+//! Every behaviour predicts a distribution over the free fields next to a player's head. The
+//! behaviours are weighted by how well they predicted the moves observed so far, and the
+//! prediction for an opponent mixes them by these weights.
+
+use super::helper::{iter_directions, move_by_direction, point_to_point_distance};
+use super::State;
+use crate::client::PlayerId;
+use crate::{MoveDirection, Position};
+use ordered_float::OrderedFloat;
+use std::fmt;
+
+/// Share of every prediction spread evenly over all free fields, so that no behaviour rules out a
+/// move completely
+const SMOOTHING: f32 = 0.1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Behaviour {
+    /// Keeps going in the same direction
+    Straight,
+    /// Moves along walls and trails
+    WallHugger,
+    /// Moves towards the nearest head of another player
+    Chaser,
+    /// Any free field is equally likely
+    Random,
+}
+
+impl Behaviour {
+    pub const ALL: [Behaviour; 4] = [
+        Behaviour::Straight,
+        Behaviour::WallHugger,
+        Behaviour::Chaser,
+        Behaviour::Random,
+    ];
+
+    /// Probability of each of the `targets` when `player` moves from `head`, after moving in
+    /// `last_direction` before
+    fn distribution(
+        self,
+        state: &State,
+        player: PlayerId,
+        head: &Position,
+        last_direction: Option<&MoveDirection>,
+        targets: &[(MoveDirection, Position)],
+    ) -> Vec<f32> {
+        let weights: Vec<f32> = match self {
+            Behaviour::Straight => targets
+                .iter()
+                .map(|(d, _p)| if Some(d) == last_direction { 1.0 } else { 0.0 })
+                .collect(),
+            Behaviour::WallHugger => targets
+                .iter()
+                .map(|(_d, p)| {
+                    iter_directions()
+                        .map(|d| move_by_direction(p, d, &state.game_size))
+                        .filter(|n| n != head && state.is_occupied(n.clone()))
+                        .count() as f32
+                })
+                .collect(),
+            Behaviour::Chaser => {
+                let nearest = state
+                    .player_heads
+                    .iter()
+                    .filter(|(other, _head)| **other != player)
+                    .map(|(_other, other_head)| other_head)
+                    .min_by_key(|other_head| {
+                        OrderedFloat(point_to_point_distance(head, other_head, &state.game_size))
+                    });
+                match nearest {
+                    Some(nearest) => {
+                        let distances: Vec<f32> = targets
+                            .iter()
+                            .map(|(_d, p)| point_to_point_distance(p, nearest, &state.game_size))
+                            .collect();
+                        let closest = distances.iter().copied().fold(f32::INFINITY, f32::min);
+                        distances
+                            .iter()
+                            .map(|d| if *d == closest { 1.0 } else { 0.0 })
+                            .collect()
+                    }
+                    None => vec![0.0; targets.len()],
+                }
+            }
+            Behaviour::Random => vec![1.0; targets.len()],
+        };
+        smoothed(&weights)
+    }
+}
+
+impl fmt::Display for Behaviour {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Behaviour::Straight => "straight",
+            Behaviour::WallHugger => "wall hugger",
+            Behaviour::Chaser => "chaser",
+            Behaviour::Random => "random",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Normalise the weights to a distribution and mix it with the uniform distribution. Without any
+/// weight, the result is uniform.
+fn smoothed(weights: &[f32]) -> Vec<f32> {
+    let total: f32 = weights.iter().sum();
+    let uniform = 1.0 / weights.len() as f32;
+    weights
+        .iter()
+        .map(|w| {
+            if total > 0.0 {
+                (1.0 - SMOOTHING) * w / total + SMOOTHING * uniform
+            } else {
+                uniform
+            }
+        })
+        .collect()
+}
+
+/// The free fields next to `head` with the directions leading to them
+fn free_targets(state: &State, head: &Position) -> Vec<(MoveDirection, Position)> {
+    let mut targets: Vec<(MoveDirection, Position)> = Vec::with_capacity(4);
+    for d in iter_directions() {
+        let p = move_by_direction(head, d, &state.game_size);
+        // Different directions lead to the same field on tiny boards
+        if !state.is_occupied(p.clone()) && !targets.iter().any(|(_d, t)| *t == p) {
+            targets.push((d.clone(), p));
+        }
+    }
+    targets
+}
+
+/// What we learned about an opponent in the current game
+#[derive(Clone, Default, Debug)]
+pub struct OpponentModel {
+    /// Directions of all observed moves
+    pub history: Vec<MoveDirection>,
+    /// Log-likelihood of the observed moves under each behaviour, in the order of
+    /// [Behaviour::ALL]
+    pub log_likelihood: [f32; 4],
+    /// Log-likelihood of each behaviour before observing any move, e.g. from earlier games
+    prior: [f32; 4],
+    /// Number of moves in which the opponent could have moved next to another head
+    pub head_on_chances: u32,
+    /// Number of moves in which the opponent moved next to another head
+    pub head_on_taken: u32,
+}
+
+impl OpponentModel {
+    /// Probability of each behaviour given the prior and the observed moves, in the order of
+    /// [Behaviour::ALL]
+    pub fn weights(&self) -> [f32; 4] {
+        let mut log_posterior = self.log_likelihood;
+        for (l, prior) in log_posterior.iter_mut().zip(self.prior) {
+            *l += prior;
+        }
+        let max = log_posterior
+            .iter()
+            .copied()
+            .fold(f32::NEG_INFINITY, f32::max);
+        let mut weights = log_posterior.map(|l| (l - max).exp());
+        let total: f32 = weights.iter().sum();
+        for w in weights.iter_mut() {
+            *w /= total;
+        }
+        weights
+    }
+
+    /// The behaviour explaining the observed moves best, with its probability
+    pub fn most_likely(&self) -> (Behaviour, f32) {
+        Behaviour::ALL
+            .iter()
+            .copied()
+            .zip(self.weights())
+            .max_by_key(|(_behaviour, w)| OrderedFloat(*w))
+            .unwrap()
+    }
+
+    pub(super) fn set_prior(&mut self, prior: [f32; 4]) {
+        self.prior = prior;
+    }
+
+    /// Add an observed move, see [observe_move].
+    pub(super) fn record(&mut self, observation: Observation) {
+        for (l, likelihood) in self.log_likelihood.iter_mut().zip(observation.likelihoods) {
+            *l += likelihood.ln();
+        }
+        if let Some(taken) = observation.head_on {
+            self.head_on_chances += 1;
+            if taken {
+                self.head_on_taken += 1;
+            }
+        }
+        self.history.push(observation.direction);
+    }
+
+    /// Distribution of the next move of `player` from `head` as (target, probability)
+    pub(super) fn predict(
+        &self,
+        state: &State,
+        player: PlayerId,
+        head: &Position,
+    ) -> Vec<(Position, f32)> {
+        let targets = free_targets(state, head);
+        let mut result: Vec<(Position, f32)> =
+            targets.iter().map(|(_d, p)| (p.clone(), 0.0)).collect();
+        for (behaviour, weight) in Behaviour::ALL.iter().zip(self.weights()) {
+            let distribution =
+                behaviour.distribution(state, player, head, self.history.last(), &targets);
+            for ((_p, probability), q) in result.iter_mut().zip(distribution) {
+                *probability += weight * q;
+            }
+        }
+        result
+    }
+}
+
+/// A move of an opponent as seen before the move
+pub(super) struct Observation {
+    direction: MoveDirection,
+    /// Probability of the move under each behaviour, in the order of [Behaviour::ALL]
+    likelihoods: [f32; 4],
+    /// Whether the player moved next to the head of another player, None if it couldn't
+    head_on: Option<bool>,
+}
+
+/// Observe the move of `player` to `to`, given the state before the move. None if `to` is not a
+/// free field next to the player's head, e.g. after missing a tick.
+pub(super) fn observe_move(state: &State, player: PlayerId, to: &Position) -> Option<Observation> {
+    let from = state.player_head(player)?;
+    let targets = free_targets(state, from);
+    let index = targets.iter().position(|(_d, p)| p == to)?;
+    let last_direction = state
+        .opponent_model(player)
+        .and_then(|model| model.history.last());
+    let likelihoods = Behaviour::ALL.map(|behaviour| {
+        behaviour.distribution(state, player, from, last_direction, &targets)[index]
+    });
+    let next_to_head = |p: &Position| {
+        iter_directions()
+            .map(|d| move_by_direction(p, d, &state.game_size))
+            .any(|n| {
+                state
+                    .player_heads
+                    .iter()
+                    .any(|(other, head)| *other != player && *head == n)
+            })
+    };
+    let head_on = if targets.iter().any(|(_d, p)| next_to_head(p)) {
+        Some(next_to_head(to))
+    } else {
+        None
+    };
+    Some(Observation {
+        direction: targets[index].0.clone(),
+        likelihoods,
+        head_on,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::scenario::{parse, player};
+    use crate::client::Answer;
+
+    /// Send the positions of one tick like the server does
+    fn tick(state: &mut State, moves: &[(char, (u32, u32))]) {
+        for (letter, (x, y)) in moves {
+            state.update_from_answer(&Answer::Pos(player(*letter), Position { x: *x, y: *y }));
+        }
+        state.update_from_answer(&Answer::Tick);
+    }
+
+    fn straight_mover() -> State {
+        let mut state = parse(
+            "
+            ..........
+            .A........
+            ..........
+            ..........
+            @.........
+            ",
+        );
+        for x in 2..6 {
+            tick(&mut state, &[('a', (x, 1))]);
+        }
+        state
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn identifies_straight_mover() {
+        let state = straight_mover();
+        let model = state.opponent_model(player('a')).unwrap();
+        assert_eq!(model.history, vec![MoveDirection::Right; 4]);
+        assert_eq!(model.most_likely().0, Behaviour::Straight);
+    }
+
+    #[test]
+    fn predicts_straight_mover_to_keep_its_direction() {
+        let state = straight_mover();
+        let prediction = state.predict_moves(player('a'));
+        let total: f32 = prediction.iter().map(|(_p, probability)| probability).sum();
+        assert!((total - 1.0).abs() < 1e-5);
+        let (target, _probability) = prediction
+            .iter()
+            .max_by_key(|(_p, probability)| OrderedFloat(*probability))
+            .unwrap();
+        assert_eq!(*target, Position { x: 6, y: 1 });
+    }
+
+    #[test]
+    fn observes_moves_against_the_board_before_the_tick() {
+        // B moves next to the new head of A, but couldn't have known where A moves
+        let mut state = parse(
+            "
+            ..........
+            .A........
+            ...B......
+            ..........
+            @.........
+            ",
+        );
+        tick(&mut state, &[('a', (2, 1)), ('b', (2, 2))]);
+        let a = state.opponent_model(player('a')).unwrap();
+        assert_eq!(a.history, vec![MoveDirection::Right]);
+        let b = state.opponent_model(player('b')).unwrap();
+        assert_eq!(b.history, vec![MoveDirection::Left]);
+        assert_eq!(b.head_on_chances, 0);
+        assert_eq!(
+            state.player_head(player('b')),
+            Some(&Position { x: 2, y: 2 })
+        );
+    }
+
+    #[test]
+    fn weights_start_from_the_prior() {
+        assert_close(&OpponentModel::default().weights(), &[0.25; 4]);
+        let mut model = OpponentModel::default();
+        model.set_prior([0.0, 2f32.ln(), 0.0, 0.0]);
+        assert_close(&model.weights(), &[0.2, 0.4, 0.2, 0.2]);
+        assert_eq!(model.most_likely().0, Behaviour::WallHugger);
+    }
+}
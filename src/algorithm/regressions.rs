@@ -0,0 +1,114 @@
+//! Kinds of situations which were deadly at GPN, rebuilt as small boards and checked for every
+//! strategy.
+
+use super::available_strategies;
+use super::scenario::{
+    assert_always_moves, assert_never_moves, assert_survives_step, decision_time, parse, player,
+};
+use crate::MoveDirection;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Duration;
+
+/// Left leads into a pocket of two fields, right into a room of ten.
+const POCKET: &str = "
+    bbbbbbbb
+    b.b.....
+    b.@.....
+    bb*bbbbB
+    ........
+";
+
+/// The only free neighbour is across the left edge of the board.
+const WRAP_AROUND_EXIT: &str = "
+    ........
+    a.......
+    @A......
+    *.......
+";
+
+/// Up leads into a dead-end corridor of three fields, left and right into a room of fifteen.
+const DEAD_END_CORRIDOR: &str = "
+    aaaaaaaaa
+    aaaa.aaaa
+    aaaa.aaaa
+    aaaa.aaaa
+    ....@....
+    ....*...A
+";
+
+#[test]
+fn avoids_pocket() {
+    for strategy in available_strategies() {
+        assert_survives_step(POCKET, strategy);
+        assert_never_moves(POCKET, strategy, MoveDirection::Left);
+    }
+}
+
+#[test]
+fn takes_exit_across_the_edge() {
+    for strategy in available_strategies() {
+        assert_always_moves(WRAP_AROUND_EXIT, strategy, MoveDirection::Left);
+    }
+}
+
+#[test]
+fn avoids_dead_end_corridor() {
+    for strategy in available_strategies() {
+        assert_survives_step(DEAD_END_CORRIDOR, strategy);
+        assert_never_moves(DEAD_END_CORRIDOR, strategy, MoveDirection::Up);
+    }
+}
+
+/// A large board with many long trails, on which every strategy needs much longer than a tick to
+/// finish.
+fn crowded_board() -> String {
+    const SIZE: usize = 256;
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut board = vec![vec!['.'; SIZE]; SIZE];
+    for letter in "@abcdefghijklmnopqrstuvwxy".chars() {
+        let (trail, head) = match letter {
+            '@' => ('*', '@'),
+            _ => (letter, letter.to_ascii_uppercase()),
+        };
+        let (mut x, mut y) = (rng.gen_range(0..SIZE), rng.gen_range(0..SIZE));
+        let mut last = None;
+        for _ in 0..400 {
+            if board[y][x] == '.' {
+                board[y][x] = trail;
+                last = Some((x, y));
+            }
+            if rng.gen_bool(0.5) {
+                x = (x + 1) % SIZE;
+            } else {
+                y = (y + 1) % SIZE;
+            }
+        }
+        let (x, y) = last.unwrap();
+        board[y][x] = head;
+    }
+    board
+        .into_iter()
+        .map(|row| row.into_iter().collect::<String>() + "\n")
+        .collect()
+}
+
+#[test]
+fn decides_within_tick_budget() {
+    const BUDGET: Duration = Duration::from_millis(20);
+    /// Time to notice the expired deadline, generous for unoptimised builds
+    const SLACK: Duration = Duration::from_millis(10);
+    let state = parse(&crowded_board());
+    assert_eq!(state.player_heads.len(), 26);
+    assert!(state.player_heads.contains_key(&player('y')));
+    for strategy in available_strategies() {
+        let elapsed = decision_time(&state, strategy, BUDGET);
+        assert!(
+            elapsed <= BUDGET + SLACK,
+            "{} took {:?} with a budget of {:?}",
+            strategy,
+            elapsed,
+            BUDGET
+        );
+    }
+}
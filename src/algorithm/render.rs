@@ -0,0 +1,123 @@
+//! Drawing the board of a [State] into a terminal with ANSI colours.
+
+use super::algorithm2::taint_fields_near_heads;
+use super::helper::{iter_directions, move_by_direction, reachable_fields, voronoi};
+use super::{Deadline, State};
+use crate::client::PlayerId;
+use crate::{AlgorithmConfig, Position};
+use serde::Deserialize;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Additional information drawn on the empty fields of the board
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Overlay {
+    #[default]
+    None,
+    /// The taint map of the `taint` strategy, darker fields are closer to opponent heads
+    Taint,
+    /// The empty region reachable from our head
+    Region,
+    /// The fields each player reaches first, contested fields in grey
+    Voronoi,
+}
+
+impl FromStr for Overlay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Overlay::None),
+            "taint" => Ok(Overlay::Taint),
+            "region" => Ok(Overlay::Region),
+            "voronoi" => Ok(Overlay::Voronoi),
+            _ => Err(format!(
+                "Unknown overlay \"{}\", available overlays: none, taint, region, voronoi",
+                s
+            )),
+        }
+    }
+}
+
+/// 256-colour palette indices for the players
+const PLAYER_COLOURS: [u8; 12] = [196, 46, 33, 226, 201, 51, 208, 93, 118, 27, 214, 163];
+
+const RESET: &str = "\x1b[0m";
+
+fn player_colour(player: PlayerId) -> u8 {
+    PLAYER_COLOURS[player.0 as usize % PLAYER_COLOURS.len()]
+}
+
+/// Render the board with one colour per player. Every field is two characters wide. Heads are
+/// drawn as `<>`, our own head as `@@`, and our own trail is drawn bold. The taint overlay uses
+/// the `taint` parameters of `config`.
+pub fn render_ansi(state: &State, overlay: Overlay, config: &AlgorithmConfig) -> String {
+    let taint = match overlay {
+        Overlay::Taint => Some(taint_fields_near_heads(
+            state,
+            &config.taint,
+            &Deadline::none(),
+        )),
+        _ => None,
+    };
+    let region = match overlay {
+        Overlay::Region => {
+            let neighbours: Vec<Position> = iter_directions()
+                .map(|d| move_by_direction(&state.my_position, d, &state.game_size))
+                .collect();
+            Some(reachable_fields(neighbours.iter(), state))
+        }
+        _ => None,
+    };
+    let partition = match overlay {
+        Overlay::Voronoi => Some(voronoi(state, &Deadline::none())),
+        _ => None,
+    };
+
+    let mut result = String::new();
+    for y in 0..state.game_size.y {
+        for x in 0..state.game_size.x {
+            let p = Position { x, y };
+            match state.field_occupation[p.as_dim()] {
+                Some(player) => {
+                    let is_head = state.player_heads.get(&player) == Some(&p);
+                    let colour = player_colour(player);
+                    let _ = match (player == state.my_id, is_head) {
+                        (true, true) => write!(result, "\x1b[1;97;48;5;{}m@@", colour),
+                        (false, true) => write!(result, "\x1b[1;97;48;5;{}m<>", colour),
+                        (true, false) => write!(result, "\x1b[1;38;5;{}m██", colour),
+                        (false, false) => write!(result, "\x1b[38;5;{}m▓▓", colour),
+                    };
+                }
+                None => {
+                    if let Some(taint) = taint.as_ref() {
+                        let grey = 232 + (taint[p.as_dim()].clamp(0.0, 1.0) * 23.0) as u8;
+                        let _ = write!(result, "\x1b[48;5;{}m  ", grey);
+                    } else if region.as_ref().is_some_and(|region| region.get(&p)) {
+                        result.push_str("\x1b[48;5;238m  ");
+                    } else if let Some(partition) = partition.as_ref() {
+                        let owner = partition
+                            .territories
+                            .iter()
+                            .find(|(_player, territory)| territory.get(&p));
+                        match owner {
+                            Some((player, _territory)) => {
+                                let _ = write!(result, "\x1b[38;5;{}m··", player_colour(*player));
+                            }
+                            None if partition.contested.get(&p) => {
+                                result.push_str("\x1b[48;5;240m  ");
+                            }
+                            None => result.push_str("\x1b[2m· "),
+                        }
+                    } else {
+                        result.push_str("\x1b[2m· ");
+                    }
+                }
+            }
+            result.push_str(RESET);
+        }
+        result.push('\n');
+    }
+    result
+}
@@ -0,0 +1,259 @@
+//! Board positions written as text for tests of the strategies.
+//!
+//! Every line of a board is a row, every character a field:
+//!
+//! - `.` is a free field
+//! - `@` is our head and `*` our trail
+//! - an uppercase letter is the head of another player and the same lowercase letter its trail
+//!
+//! Blank lines and the indentation of the rows are ignored. The board wraps
+//! around at the edges like in the game, so enclosed areas need trails on all sides. We are
+//! [PlayerId] 0, the player of `a` is 1, the one of `b` is 2, and so on.
+
+use super::{create_strategy, decide_action, Deadline, State};
+use crate::client::{Answer, PlayerId};
+use crate::{AlgorithmConfig, MoveDirection, Position};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Number of seeds every strategy is asked with, to cover its random choices
+const SEEDS: u64 = 8;
+
+const US: PlayerId = PlayerId(0);
+
+/// The player of a head or trail letter
+pub(super) fn player(letter: char) -> PlayerId {
+    assert!(letter.is_ascii_alphabetic(), "{:?} is not a player", letter);
+    PlayerId(letter.to_ascii_lowercase() as u32 - 'a' as u32 + 1)
+}
+
+/// Parse a board into the state we would have when the positions were sent by the server, without
+/// any observed moves of the opponents.
+///
+/// Panics if the board is malformed, e.g. rows of different length or a player without a head.
+pub(super) fn parse(board: &str) -> State {
+    let rows: Vec<&str> = board
+        .lines()
+        .map(|row| row.trim())
+        .filter(|row| !row.is_empty())
+        .collect();
+    let width = rows[0].chars().count();
+    assert!(
+        rows.iter().all(|row| row.chars().count() == width),
+        "All rows of the board must have the same length"
+    );
+    let size = Position {
+        x: width as u32,
+        y: rows.len() as u32,
+    };
+
+    let mut trails: BTreeMap<PlayerId, Vec<Position>> = BTreeMap::new();
+    let mut heads: BTreeMap<PlayerId, Position> = BTreeMap::new();
+    for (y, row) in rows.iter().enumerate() {
+        for (x, field) in row.chars().enumerate() {
+            let position = Position {
+                x: x as u32,
+                y: y as u32,
+            };
+            let (player, is_head) = match field {
+                '.' => continue,
+                '@' => (US, true),
+                '*' => (US, false),
+                letter if letter.is_ascii_alphabetic() => {
+                    (player(letter), letter.is_ascii_uppercase())
+                }
+                other => panic!("Unknown field {:?} in the board", other),
+            };
+            if is_head {
+                let previous = heads.insert(player, position);
+                assert!(previous.is_none(), "Player {} has two heads", player.0);
+            } else {
+                trails.entry(player).or_default().push(position);
+            }
+        }
+    }
+    assert!(heads.contains_key(&US), "The board has no @");
+
+    let mut state = State::default();
+    state.update_from_answer(&Answer::Game(size, US));
+    for (player, trail) in trails.iter() {
+        assert!(
+            heads.contains_key(player),
+            "Player {} has a trail but no head",
+            player.0
+        );
+        for position in trail {
+            state.field_occupation[position.as_dim()] = Some(*player);
+            state.occupied.set(position);
+        }
+    }
+    // The fields are set directly, since the trails are not the order in which the players moved
+    // and must not show up as observed moves of the opponents
+    for (player, head) in heads {
+        if player == US {
+            state.my_position = head.clone();
+        }
+        state.field_occupation[head.as_dim()] = Some(player);
+        state.occupied.set(&head);
+        state.player_heads.insert(player, head);
+    }
+    state
+}
+
+/// The direction the strategy chooses on the board with each of the seeds
+pub(super) fn decisions(board: &str, strategy: &str) -> Vec<Option<MoveDirection>> {
+    let config = AlgorithmConfig {
+        algorithm: strategy.to_owned(),
+        ..AlgorithmConfig::default()
+    };
+    (0..SEEDS)
+        .map(|seed| {
+            let mut state = parse(board);
+            let mut strategy = create_strategy(&config).unwrap();
+            strategy.init(&state);
+            let mut rng = StdRng::seed_from_u64(seed);
+            decide_action(&mut state, &mut rng, strategy.as_mut(), &Deadline::none()).direction
+        })
+        .collect()
+}
+
+/// Time the strategy takes to decide on a copy of the state with the given tick budget, including
+/// the fallback move if it runs out of time
+pub(super) fn decision_time(state: &State, strategy: &str, budget: Duration) -> Duration {
+    let config = AlgorithmConfig {
+        algorithm: strategy.to_owned(),
+        ..AlgorithmConfig::default()
+    };
+    let mut state = state.clone();
+    let mut strategy = create_strategy(&config).unwrap();
+    strategy.init(&state);
+    let mut rng = StdRng::seed_from_u64(0);
+    let start = Instant::now();
+    decide_action(
+        &mut state,
+        &mut rng,
+        strategy.as_mut(),
+        &Deadline::after(budget),
+    );
+    start.elapsed()
+}
+
+/// Assert that the strategy never moves in the direction on the board.
+pub(super) fn assert_never_moves(board: &str, strategy: &str, direction: MoveDirection) {
+    for (seed, decision) in decisions(board, strategy).into_iter().enumerate() {
+        assert_ne!(
+            decision,
+            Some(direction.clone()),
+            "{} moved {:?} with seed {} on{}",
+            strategy,
+            direction,
+            seed,
+            board
+        );
+    }
+}
+
+/// Assert that the strategy always moves in the direction on the board.
+pub(super) fn assert_always_moves(board: &str, strategy: &str, direction: MoveDirection) {
+    for (seed, decision) in decisions(board, strategy).into_iter().enumerate() {
+        assert_eq!(
+            decision,
+            Some(direction.clone()),
+            "{} moved {:?} instead of {:?} with seed {} on{}",
+            strategy,
+            decision,
+            direction,
+            seed,
+            board
+        );
+    }
+}
+
+/// Assert that the strategy always moves to a free field on the board.
+pub(super) fn assert_survives_step(board: &str, strategy: &str) {
+    let state = parse(board);
+    for (seed, decision) in decisions(board, strategy).into_iter().enumerate() {
+        let target = decision.as_ref().map(|direction| {
+            super::helper::move_by_direction(&state.my_position, direction, &state.game_size)
+        });
+        assert!(
+            target.is_some_and(|target| !state.is_occupied(target)),
+            "{} moved {:?} onto an occupied field with seed {} on{}",
+            strategy,
+            decision,
+            seed,
+            board
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_board() {
+        let state = parse(
+            "
+            ..a.
+            *@A.
+            .bB.
+            ",
+        );
+        assert_eq!(state.game_size, Position { x: 4, y: 3 });
+        assert_eq!(state.my_id, US);
+        assert_eq!(state.my_position, Position { x: 1, y: 1 });
+        assert_eq!(state.player_heads.len(), 3);
+        assert_eq!(
+            state.player_head(player('a')),
+            Some(&Position { x: 2, y: 1 })
+        );
+        assert_eq!(
+            state.player_head(player('B')),
+            Some(&Position { x: 2, y: 2 })
+        );
+        assert_eq!(state.field_occupation[(0, 1)], Some(US));
+        assert_eq!(state.field_occupation[(2, 0)], Some(player('a')));
+        assert_eq!(state.field_occupation[(1, 2)], Some(player('b')));
+        assert_eq!(state.field_occupation[(3, 0)], None);
+        assert_eq!(state.field_occupation.iter().flatten().count(), 6);
+        assert!(state.is_occupied(Position { x: 2, y: 2 }));
+        assert!(!state.is_occupied(Position { x: 0, y: 0 }));
+    }
+
+    #[test]
+    fn parsed_opponents_have_no_history() {
+        let state = parse(
+            "
+            aaaA
+            .@..
+            ",
+        );
+        assert!(state.opponent_model(player('a')).is_none());
+        assert!(state.opponent_models().next().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "trail but no head")]
+    fn rejects_trail_without_head() {
+        parse(
+            "
+            @a
+            ..
+            ",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "same length")]
+    fn rejects_ragged_rows() {
+        parse(
+            "
+            @A.
+            ..
+            ",
+        );
+    }
+}
@@ -0,0 +1,18 @@
+use gpn21_tron::server::GameServerConfig;
+use log::error;
+use std::{env, fs};
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = env::args().collect();
+    let config = if args.len() > 1 {
+        let config_string = fs::read_to_string(&args[1]).unwrap();
+        toml::from_str(&config_string).unwrap()
+    } else {
+        GameServerConfig::default()
+    };
+
+    if let Err(e) = gpn21_tron::server::run(&config) {
+        error!("Server error: {}", e);
+    }
+}
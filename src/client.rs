@@ -1,11 +1,12 @@
 use crate::{MoveDirection, Position};
-use log::{debug, warn};
-use std::io;
+use log::debug;
 use std::io::{BufRead, BufReader, Write};
 use std::net::TcpStream;
+use std::str::FromStr;
+use std::{fmt, io};
 
-#[derive(Default, Clone, Eq, PartialEq, Hash, Copy, Debug)]
-pub struct PlayerId(u32);
+#[derive(Default, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Debug)]
+pub struct PlayerId(pub u32);
 
 #[derive(Debug)]
 pub enum Command<'a> {
@@ -14,6 +15,7 @@ pub enum Command<'a> {
     Chat(&'a str),
 }
 
+#[derive(Debug, PartialEq, Eq)]
 pub enum Answer {
     Motd(String),
     Error(String),
@@ -25,65 +27,136 @@ pub enum Answer {
     Die(Vec<PlayerId>),
     Message(PlayerId, String),
     Player(PlayerId, String),
+    /// A message of a type we don't know, e.g. from a newer server, as the complete line
+    Unknown(String),
 }
 
-pub fn get_answer(reader: &mut BufReader<TcpStream>) -> io::Result<Option<Answer>> {
+/// A line from the server which could not be parsed into an [Answer].
+#[derive(Debug)]
+pub struct ProtocolError {
+    /// The complete line as received from the server
+    pub line: String,
+    /// Name of the field which could not be parsed
+    pub field: &'static str,
+    pub reason: String,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Malformed line \"{}\": invalid {}: {}",
+            self.line, self.field, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Read the next raw line from the server.
+pub fn read_line(reader: &mut BufReader<TcpStream>) -> io::Result<String> {
     let mut command = String::new();
-    reader.read_line(&mut command)?;
+    if reader.read_line(&mut command)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Empty answer from server.",
+        ));
+    }
     debug!("Received answer: {}", command.trim());
-    let mut parts = command.trim().split("|");
-    Ok(match parts.next().unwrap() {
-        "motd" => Some(Answer::Motd(parts.next().unwrap_or("").to_owned())),
-        "error" => Some(Answer::Error(parts.next().unwrap_or("").to_owned())),
-        "pos" => Some(Answer::Pos(
-            PlayerId(parts.next().unwrap_or("0").parse().unwrap_or(0)),
+    Ok(command)
+}
+
+struct Fields<'a> {
+    line: &'a str,
+    parts: std::str::Split<'a, char>,
+}
+
+impl<'a> Fields<'a> {
+    fn error(&self, field: &'static str, reason: impl ToString) -> ProtocolError {
+        ProtocolError {
+            line: self.line.to_owned(),
+            field,
+            reason: reason.to_string(),
+        }
+    }
+
+    fn next<T: FromStr>(&mut self, field: &'static str) -> Result<T, ProtocolError>
+    where
+        T::Err: fmt::Display,
+    {
+        let part = self
+            .parts
+            .next()
+            .ok_or_else(|| self.error(field, "missing"))?;
+        part.parse()
+            .map_err(|e| self.error(field, format!("\"{}\": {}", part, e)))
+    }
+
+    fn player(&mut self) -> Result<PlayerId, ProtocolError> {
+        Ok(PlayerId(self.next("player id")?))
+    }
+
+    /// All remaining fields, for free text which may contain the separator itself
+    fn text(&mut self) -> String {
+        self.parts.by_ref().collect::<Vec<_>>().join("|")
+    }
+}
+
+/// Parse a single line received from the server. Lines of unknown types are no error, so that
+/// new message types of the server don't break the bot.
+pub fn parse_answer(line: &str) -> Result<Answer, ProtocolError> {
+    let line = line.trim();
+    let mut fields = Fields {
+        line,
+        parts: line.split('|'),
+    };
+    Ok(match fields.parts.next().unwrap() {
+        "motd" => Answer::Motd(fields.text()),
+        "error" => Answer::Error(fields.text()),
+        "pos" => Answer::Pos(
+            fields.player()?,
             Position {
-                x: parts.next().unwrap_or("0").parse().unwrap_or(0),
-                y: parts.next().unwrap_or("0").parse().unwrap_or(0),
+                x: fields.next("x")?,
+                y: fields.next("y")?,
             },
-        )),
-        "win" => Some(Answer::Win(
-            parts.next().unwrap_or("0").parse().unwrap_or(0),
-            parts.next().unwrap_or("0").parse().unwrap_or(0),
-        )),
-        "lose" => Some(Answer::Lose(
-            parts.next().unwrap_or("0").parse().unwrap_or(0),
-            parts.next().unwrap_or("0").parse().unwrap_or(0),
-        )),
-        "game" => Some(Answer::Game(
+        ),
+        "win" => Answer::Win(fields.next("wins")?, fields.next("losses")?),
+        "lose" => Answer::Lose(fields.next("wins")?, fields.next("losses")?),
+        "game" => Answer::Game(
             Position {
-                x: parts.next().unwrap_or("0").parse().unwrap_or(0),
-                y: parts.next().unwrap_or("0").parse().unwrap_or(0),
+                x: fields.next("width")?,
+                y: fields.next("height")?,
             },
-            PlayerId(parts.next().unwrap_or("0").parse().unwrap_or(0)),
-        )),
-        "tick" => Some(Answer::Tick),
-        "die" => Some(Answer::Die(
-            parts.map(|id| PlayerId(id.parse().unwrap_or(0))).collect(),
-        )),
-        "message" => Some(Answer::Message(
-            PlayerId(parts.next().unwrap_or("0").parse().unwrap_or(0)),
-            parts.next().unwrap_or("").to_string(),
-        )),
-        "player" => Some(Answer::Player(
-            PlayerId(parts.next().unwrap_or("0").parse().unwrap_or(0)),
-            parts.next().unwrap_or("").to_string(),
-        )),
-        "" => {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Empty answer from server.",
-            ));
-        }
-        x => {
-            warn!("Unkown message from server: {}", x);
-            None
+            fields.player()?,
+        ),
+        "tick" => Answer::Tick,
+        "die" => {
+            let mut players = Vec::new();
+            // No players are sent as "die" or "die|"
+            if !fields.parts.clone().eq([""].iter().copied()) {
+                while fields.parts.clone().next().is_some() {
+                    players.push(fields.player()?);
+                }
+            }
+            Answer::Die(players)
         }
+        "message" => Answer::Message(fields.player()?, fields.text()),
+        "player" => Answer::Player(fields.player()?, fields.text()),
+        "" => return Err(fields.error("message type", "empty line")),
+        _ => Answer::Unknown(line.to_owned()),
     })
 }
 
 pub fn send_command(stream: &mut TcpStream, command: &Command) -> io::Result<()> {
-    let data = match command {
+    let data = format_command(command);
+    debug!("Sending command: {}", data.trim());
+    stream.write_all(data.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+pub fn format_command(command: &Command) -> String {
+    match command {
         Command::Join(user, password) => format!("join|{}|{}\n", user, password),
         Command::Move(direction) => format!(
             "move|{}\n",
@@ -95,9 +168,163 @@ pub fn send_command(stream: &mut TcpStream, command: &Command) -> io::Result<()>
             }
         ),
         Command::Chat(msg) => format!("chat|{}\n", msg),
-    };
-    debug!("Sending command: {}", data.trim());
-    stream.write(data.as_bytes())?;
-    stream.flush()?;
-    Ok(())
+    }
+}
+
+/// Parse a command line sent by a client. This is the server side counterpart of
+/// [format_command].
+pub fn parse_command(line: &str) -> Option<Command<'_>> {
+    let mut parts = line.trim().split('|');
+    match parts.next()? {
+        "join" => Some(Command::Join(parts.next()?, parts.next().unwrap_or(""))),
+        "move" => Some(Command::Move(match parts.next()? {
+            "up" => MoveDirection::Up,
+            "right" => MoveDirection::Right,
+            "down" => MoveDirection::Down,
+            "left" => MoveDirection::Left,
+            _ => return None,
+        })),
+        "chat" => Some(Command::Chat(parts.next().unwrap_or(""))),
+        _ => None,
+    }
+}
+
+/// Serialize an answer into a protocol line. This is the server side counterpart of
+/// [parse_answer].
+pub fn format_answer(answer: &Answer) -> String {
+    match answer {
+        Answer::Motd(msg) => format!("motd|{}\n", msg),
+        Answer::Error(msg) => format!("error|{}\n", msg),
+        Answer::Pos(PlayerId(p), position) => {
+            format!("pos|{}|{}|{}\n", p, position.x, position.y)
+        }
+        Answer::Win(wins, losses) => format!("win|{}|{}\n", wins, losses),
+        Answer::Lose(wins, losses) => format!("lose|{}|{}\n", wins, losses),
+        Answer::Game(size, PlayerId(p)) => format!("game|{}|{}|{}\n", size.x, size.y, p),
+        Answer::Tick => "tick\n".to_owned(),
+        Answer::Die(players) => format!(
+            "die|{}\n",
+            players
+                .iter()
+                .map(|PlayerId(p)| p.to_string())
+                .collect::<Vec<_>>()
+                .join("|")
+        ),
+        Answer::Message(PlayerId(p), msg) => format!("message|{}|{}\n", p, msg),
+        Answer::Player(PlayerId(p), name) => format!("player|{}|{}\n", p, name),
+        Answer::Unknown(line) => format!("{}\n", line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Answer {
+        parse_answer(line).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn parse_error(line: &str) -> ProtocolError {
+        match parse_answer(line) {
+            Ok(answer) => panic!("Parsed \"{}\" as {:?}", line, answer),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn parses_all_message_types() {
+        assert_eq!(parse("motd|Hello\n"), Answer::Motd("Hello".to_owned()));
+        assert_eq!(parse("error|Oops"), Answer::Error("Oops".to_owned()));
+        assert_eq!(
+            parse("pos|3|10|20"),
+            Answer::Pos(PlayerId(3), Position { x: 10, y: 20 })
+        );
+        assert_eq!(parse("win|5|2"), Answer::Win(5, 2));
+        assert_eq!(parse("lose|1|7"), Answer::Lose(1, 7));
+        assert_eq!(
+            parse("game|40|30|2"),
+            Answer::Game(Position { x: 40, y: 30 }, PlayerId(2))
+        );
+        assert_eq!(parse("tick\r\n"), Answer::Tick);
+        assert_eq!(
+            parse("die|1|4|9"),
+            Answer::Die(vec![PlayerId(1), PlayerId(4), PlayerId(9)])
+        );
+        assert_eq!(
+            parse("message|6|gl hf"),
+            Answer::Message(PlayerId(6), "gl hf".to_owned())
+        );
+        assert_eq!(
+            parse("player|0|somebody"),
+            Answer::Player(PlayerId(0), "somebody".to_owned())
+        );
+    }
+
+    #[test]
+    fn keeps_separator_in_text() {
+        assert_eq!(parse("motd|a|b||c"), Answer::Motd("a|b||c".to_owned()));
+        assert_eq!(
+            parse("message|1|x | y"),
+            Answer::Message(PlayerId(1), "x | y".to_owned())
+        );
+        assert_eq!(
+            parse("player|2|name|with|bars"),
+            Answer::Player(PlayerId(2), "name|with|bars".to_owned())
+        );
+    }
+
+    #[test]
+    fn parses_empty_die() {
+        assert_eq!(parse("die"), Answer::Die(Vec::new()));
+        assert_eq!(parse("die|"), Answer::Die(Vec::new()));
+        assert_eq!(parse_error("die|1||2").field, "player id");
+    }
+
+    #[test]
+    fn rejects_missing_field() {
+        let error = parse_error("pos|3|10");
+        assert_eq!(error.field, "y");
+        assert_eq!(error.reason, "missing");
+        assert_eq!(error.line, "pos|3|10");
+        assert_eq!(parse_error("game|40|30").field, "player id");
+        assert_eq!(parse_error("message").field, "player id");
+    }
+
+    #[test]
+    fn rejects_non_numeric_field() {
+        let error = parse_error("pos|3|ten|20");
+        assert_eq!(error.field, "x");
+        assert!(error.reason.starts_with("\"ten\""), "{}", error.reason);
+        assert_eq!(parse_error("win|5|-2").field, "losses");
+        assert_eq!(parse_error("die|1|x").field, "player id");
+    }
+
+    #[test]
+    fn rejects_empty_line() {
+        assert_eq!(parse_error("").reason, "empty line");
+        assert_eq!(parse_error(" \n").reason, "empty line");
+    }
+
+    #[test]
+    fn keeps_unknown_type() {
+        assert_eq!(
+            parse("teleport|1|2\n"),
+            Answer::Unknown("teleport|1|2".to_owned())
+        );
+    }
+
+    #[test]
+    fn parses_formatted_answers() {
+        let answers = vec![
+            Answer::Motd("a|b".to_owned()),
+            Answer::Pos(PlayerId(1), Position { x: 2, y: 3 }),
+            Answer::Game(Position { x: 8, y: 9 }, PlayerId(0)),
+            Answer::Die(Vec::new()),
+            Answer::Die(vec![PlayerId(4), PlayerId(5)]),
+            Answer::Player(PlayerId(7), "bot".to_owned()),
+        ];
+        for answer in answers {
+            assert_eq!(parse(&format_answer(&answer)), answer);
+        }
+    }
 }
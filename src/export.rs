@@ -0,0 +1,124 @@
+//! Export of recorded games as SVG and PNG frames and as animated GIF.
+
+use crate::algorithm::frame::{palette, render_pixels, render_svg, FrameAnnotation, PaletteImage};
+use crate::algorithm::{decide_action, Deadline};
+use crate::client::{parse_command, Command};
+use crate::replay::for_each_tick;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+pub struct ExportOptions {
+    /// Write one SVG file per tick
+    pub svg: bool,
+    /// Write one PNG file per tick
+    pub png: bool,
+    /// Write an animated GIF of the whole game
+    pub gif: bool,
+    /// Pixels per field
+    pub scale: u32,
+    pub frame_delay_ms: u32,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            svg: false,
+            png: false,
+            gif: true,
+            scale: 12,
+            frame_delay_ms: 200,
+        }
+    }
+}
+
+/// Export every tick of the recording at `path` into the `output` directory. Each frame is
+/// annotated with the direction sent in the recorded game and the candidate scores of the recorded
+/// strategy.
+/// Returns the number of exported frames.
+pub fn export(
+    path: &Path,
+    output: &Path,
+    options: &ExportOptions,
+) -> Result<usize, Box<dyn Error>> {
+    fs::create_dir_all(output)?;
+    let mut frames = Vec::new();
+    let mut result = Ok(());
+
+    for_each_tick(path, None, |recorded| {
+        if result.is_err() {
+            return;
+        }
+        let annotation = FrameAnnotation {
+            deaths: recorded.deaths.to_vec(),
+            chosen: match recorded.sent.and_then(parse_command) {
+                Some(Command::Move(direction)) => Some(direction),
+                _ => None,
+            },
+            scores: decide_action(
+                recorded.state,
+                recorded.rng,
+                recorded.strategy,
+                &Deadline::none(),
+            )
+            .candidates,
+        };
+        let name = format!("tick-{:04}", recorded.tick);
+        if options.svg {
+            let svg = render_svg(recorded.state, &annotation, options.scale);
+            if let Err(e) = fs::write(output.join(format!("{}.svg", name)), svg) {
+                result = Err(e.into());
+            }
+        }
+        if options.png || options.gif {
+            let image = render_pixels(recorded.state, &annotation, options.scale);
+            if options.png {
+                if let Err(e) = write_png(&output.join(format!("{}.png", name)), &image) {
+                    result = Err(e);
+                }
+            }
+            if options.gif {
+                frames.push(image);
+            }
+        }
+    })?;
+    result?;
+
+    let num_frames = frames.len();
+    if options.gif && !frames.is_empty() {
+        write_gif(&output.join("game.gif"), &frames, options.frame_delay_ms)?;
+    }
+    Ok(num_frames)
+}
+
+fn write_png(path: &Path, image: &PaletteImage) -> Result<(), Box<dyn Error>> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        image.width as u32,
+        image.height as u32,
+    );
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette());
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&image.pixels)?;
+    Ok(())
+}
+
+fn write_gif(path: &Path, frames: &[PaletteImage], delay_ms: u32) -> Result<(), Box<dyn Error>> {
+    let mut encoder = gif::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        frames[0].width,
+        frames[0].height,
+        &palette(),
+    )?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+    for image in frames {
+        let mut frame =
+            gif::Frame::from_indexed_pixels(image.width, image.height, &image.pixels, None);
+        frame.delay = (delay_ms / 10) as u16;
+        encoder.write_frame(&frame)?;
+    }
+    Ok(())
+}
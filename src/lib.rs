@@ -1,14 +1,33 @@
-use crate::algorithm::{decide_action, State};
-use crate::client::{send_command, Answer, Command};
-use log::{error, info, warn};
-use rand::prelude::ThreadRng;
-use serde::Deserialize;
+use crate::algorithm::render::{render_ansi, Overlay};
+pub use crate::algorithm::{
+    available_strategies, create_strategy, CandidateScore, Deadline, Decision, Strategy,
+    UnknownStrategy,
+};
+use crate::algorithm::{
+    decide_action, FloodConfig, HybridConfig, MctsConfig, RankedConfig, SearchConfig, State,
+    TaintConfig, TimingStats,
+};
+use crate::client::{format_command, send_command, Answer, Command, PlayerId};
+use crate::profiles::{Profiles, ProfilesConfig};
+use crate::recording::{Recorder, RecordingConfig};
+use log::{debug, error, info, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::io::BufReader;
 use std::net::TcpStream;
 use std::{io, thread, time};
 
 mod algorithm;
 mod client;
+pub mod export;
+mod profiles;
+mod recording;
+pub mod replay;
+pub mod server;
+pub mod simulator;
+pub mod tournament;
+pub mod tuner;
 
 #[derive(Default, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct Position {
@@ -22,7 +41,7 @@ impl Position {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub enum MoveDirection {
     Up,
     Right,
@@ -33,6 +52,19 @@ pub enum MoveDirection {
 #[derive(Deserialize)]
 pub struct ServerConfig {
     pub address: String,
+    #[serde(default)]
+    pub on_protocol_error: ProtocolErrorPolicy,
+}
+
+/// What to do when the server sends a line which is not a valid protocol message
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ProtocolErrorPolicy {
+    /// Log the line and continue with the next one
+    #[default]
+    Skip,
+    /// Close the connection and reconnect
+    Disconnect,
 }
 
 #[derive(Deserialize)]
@@ -41,17 +73,109 @@ struct UserConfig {
     password: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct AlgorithmConfig {
+    #[serde(
+        default = "default_algorithm",
+        deserialize_with = "deserialize_algorithm"
+    )]
+    algorithm: String,
+    /// Time in milliseconds to decide on a move after receiving a tick. Without a budget, the
+    /// strategies always run until they are finished.
+    #[serde(default)]
+    tick_budget_ms: Option<u64>,
+    /// Play for survival instead of using the strategy once no opponent can reach us anymore
+    #[serde(default = "default_endgame")]
+    endgame: bool,
+    /// Parameters of the `flood` strategy
+    #[serde(default)]
+    flood: FloodConfig,
+    /// Parameters of the `taint` strategy
+    #[serde(default)]
+    taint: TaintConfig,
+    /// Parameters of the `hybrid` strategy
     #[serde(default)]
-    algorithm: u32,
+    hybrid: HybridConfig,
+    /// Parameters of the `ranked` strategy, also used by `chamber`
+    #[serde(default)]
+    ranked: RankedConfig,
+    /// Parameters of the `search` strategy
+    #[serde(default)]
+    search: SearchConfig,
+    /// Parameters of the `mcts` strategy
+    #[serde(default)]
+    mcts: MctsConfig,
+}
+
+fn default_algorithm() -> String {
+    "flood".to_owned()
+}
+
+/// Names of the strategies which older configs selected by their number.
+const LEGACY_ALGORITHMS: [&str; 4] = ["flood", "taint", "hybrid", "ranked"];
+
+/// Accept the strategy name as well as the numbers `0` to `3` of older configs.
+fn deserialize_algorithm<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Algorithm {
+        Name(String),
+        Legacy(u64),
+    }
+
+    match Algorithm::deserialize(deserializer)? {
+        Algorithm::Name(name) => Ok(name),
+        Algorithm::Legacy(number) => LEGACY_ALGORITHMS
+            .get(number as usize)
+            .map(|name| (*name).to_owned())
+            .ok_or_else(|| {
+                serde::de::Error::custom(format!("unknown algorithm variant {}", number))
+            }),
+    }
+}
+
+fn default_endgame() -> bool {
+    true
+}
+
+impl Default for AlgorithmConfig {
+    fn default() -> Self {
+        AlgorithmConfig {
+            algorithm: default_algorithm(),
+            tick_budget_ms: None,
+            endgame: default_endgame(),
+            flood: FloodConfig::default(),
+            taint: TaintConfig::default(),
+            hybrid: HybridConfig::default(),
+            ranked: RankedConfig::default(),
+            search: SearchConfig::default(),
+            mcts: MctsConfig::default(),
+        }
+    }
 }
 
 #[derive(Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
     user: UserConfig,
-    algorithm: AlgorithmConfig,
+    pub algorithm: AlgorithmConfig,
+    /// Record every game to a file if given
+    recording: Option<RecordingConfig>,
+    /// Draw the board to stdout at every tick if given
+    render: Option<RenderConfig>,
+    /// Keep statistics about the other bots across games if given
+    profiles: Option<ProfilesConfig>,
+    /// Seed the seeds of all games are drawn from. Without a seed, every run differs.
+    pub seed: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct RenderConfig {
+    #[serde(default)]
+    overlay: Overlay,
 }
 
 pub fn get_connection(config: &ServerConfig) -> TcpStream {
@@ -68,9 +192,17 @@ pub fn game_loop(
     config: &Config,
     stream: &mut TcpStream,
     stream_reader: &mut BufReader<TcpStream>,
-    rng: &mut ThreadRng,
+    rng: &mut StdRng,
+    strategy: &mut dyn Strategy,
 ) -> io::Result<()> {
     let mut state = State::default();
+    let mut game_rng = StdRng::from_rng(&mut *rng).unwrap();
+    let mut timing = TimingStats::default();
+    let mut recorder = config
+        .recording
+        .as_ref()
+        .map(|c| Recorder::new(c, &config.user.user, &config.algorithm));
+    let mut profiles = config.profiles.as_ref().map(Profiles::load);
     info!("Joining game as {}", config.user.user);
     send_command(
         stream,
@@ -78,33 +210,156 @@ pub fn game_loop(
     )?;
     info!("Starting game loop.");
     loop {
-        if let Some(answer) = client::get_answer(stream_reader)? {
-            match &answer {
-                Answer::Motd(msg) => {
-                    warn!("Message of the day: {}", msg);
+        let line = client::read_line(stream_reader)?;
+        let parsed = client::parse_answer(&line);
+        if let Ok(Answer::Game(_, _)) = parsed {
+            // Every game gets its own seed, so that it can be replayed exactly
+            let seed = rng.gen();
+            info!("Game seed: {}", seed);
+            game_rng = StdRng::seed_from_u64(seed);
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.start_game(seed);
+            }
+        }
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.received(&line);
+        }
+        let answer = match parsed {
+            Ok(answer) => answer,
+            Err(e) => match config.server.on_protocol_error {
+                ProtocolErrorPolicy::Skip => {
+                    warn!("Skipping line from server: {}", e);
+                    continue;
+                }
+                ProtocolErrorPolicy::Disconnect => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+            },
+        };
+        match &answer {
+            Answer::Motd(msg) => {
+                warn!("Message of the day: {}", msg);
+            }
+            Answer::Unknown(line) => {
+                warn!("Unknown message from server: {}", line);
+            }
+            Answer::Error(msg) => {
+                warn!("Error from Server: {}", msg);
+                if msg.contains("kicked") {
+                    return Err(io::Error::from(io::ErrorKind::Other));
                 }
-                Answer::Error(msg) => {
-                    warn!("Error from Server: {}", msg);
-                    if msg.contains("kicked") {
-                        return Err(io::Error::from(io::ErrorKind::Other));
+            }
+            Answer::Die(players) => {
+                for player in players {
+                    if let Some(model) = state.opponent_model(*player) {
+                        let (behaviour, probability) = model.most_likely();
+                        info!(
+                            "Player {} played like a {} ({:.0}%) in {} moves",
+                            player.0,
+                            behaviour,
+                            probability * 100.0,
+                            model.history.len()
+                        );
                     }
                 }
-                Answer::Win(_, _) => {
-                    warn!("We won!");
+                if let Some(profiles) = profiles.as_mut() {
+                    update_profiles(profiles, &state, players);
+                }
+                if players.contains(&state.my_id()) {
+                    strategy.on_death(&state);
+                }
+            }
+            _ => {}
+        }
+        // Dead players are still on the board above. Below, the opponents' moves of the tick are
+        // observed.
+        state.update_from_answer(&answer);
+        match &answer {
+            Answer::Game(_, _) => {
+                timing = TimingStats::default();
+                strategy.init(&state);
+            }
+            Answer::Win(_, _) => {
+                warn!("We won!");
+                strategy.on_game_end(&state, true);
+                if let Some(profiles) = profiles.as_mut() {
+                    let survivors: Vec<PlayerId> = state.living_players().collect();
+                    update_profiles(profiles, &state, &survivors);
+                    profiles.save();
                 }
-                Answer::Lose(_, _) => {
-                    warn!("We lost!");
+                info!("Timing: {}", timing);
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.flush();
                 }
-                Answer::Tick => {
-                    info!("Tick.");
-                    if let Some(command) = decide_action(&mut state, rng, &config.algorithm) {
-                        info!("Command: {:?}", command);
-                        client::send_command(stream, &command)?;
+            }
+            Answer::Lose(_, _) => {
+                warn!("We lost!");
+                strategy.on_game_end(&state, false);
+                if let Some(profiles) = profiles.as_mut() {
+                    let survivors: Vec<PlayerId> = state.living_players().collect();
+                    update_profiles(profiles, &state, &survivors);
+                    profiles.save();
+                }
+                info!("Timing: {}", timing);
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.flush();
+                }
+            }
+            Answer::Tick => {
+                info!("Tick.");
+                let start = time::Instant::now();
+                let deadline = match config.algorithm.tick_budget_ms {
+                    Some(budget) => Deadline::after(time::Duration::from_millis(budget)),
+                    None => Deadline::none(),
+                };
+                if let Some(render) = config.render.as_ref() {
+                    print!(
+                        "\x1b[H\x1b[2J{}",
+                        render_ansi(&state, render.overlay, &config.algorithm)
+                    );
+                }
+                let decision = decide_action(&mut state, &mut game_rng, strategy, &deadline);
+                timing.record(start.elapsed(), &decision);
+                for candidate in decision.candidates.iter() {
+                    debug!("{}", candidate);
+                }
+                if let Some(command) = decision.command() {
+                    info!("Command: {:?}", command);
+                    client::send_command(stream, &command)?;
+                    if let Some(recorder) = recorder.as_mut() {
+                        recorder.sent(&format_command(&command));
                     }
                 }
-                _ => {}
             }
-            state.update_from_answer(&answer);
+            Answer::Player(player, name) if *player != state.my_id() => {
+                if let Some(profile) = profiles.as_ref().and_then(|p| p.get(name)) {
+                    info!(
+                        "Known bot {}: {} games, {:.1} ticks survived on average, moved next to \
+                         other heads in {:.0}% of the chances",
+                        name,
+                        profile.games,
+                        profile.average_survival(),
+                        profile.aggressiveness().unwrap_or(0.0) * 100.0
+                    );
+                    let prior = profile.behaviour_prior();
+                    state.set_behaviour_prior(*player, prior);
+                    if let Some(recorder) = recorder.as_mut() {
+                        recorder.prior(*player, prior);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Add what we learned about the given opponents in the current game to their profiles. Players
+/// without observed moves are recorded too, so that their short survival counts.
+fn update_profiles(profiles: &mut Profiles, state: &State, players: &[PlayerId]) {
+    for player in players.iter().filter(|player| **player != state.my_id()) {
+        if let Some(name) = state.player_name(*player) {
+            let model = state.opponent_model(*player).cloned().unwrap_or_default();
+            profiles.record_game(name, &model, state.tick());
         }
     }
 }
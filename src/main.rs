@@ -1,26 +1,333 @@
-use gpn21_tron::Config;
-use log::error;
+use gpn21_tron::export::ExportOptions;
+use gpn21_tron::replay::Overlay;
+use gpn21_tron::simulator::{run_simulation, SimulationConfig};
+use gpn21_tron::tournament::{run_tournament, TournamentConfig};
+use gpn21_tron::tuner::{tune, TuningConfig};
+use gpn21_tron::{AlgorithmConfig, Config};
+use log::{error, info};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::io::BufReader;
+use std::path::Path;
+use std::process::exit;
+use std::time::Instant;
 use std::{env, fs};
 
 fn main() {
     env_logger::init();
     let args: Vec<String> = env::args().collect();
-    let config_file = if args.len() > 1 {
-        &args[1]
-    } else {
-        "config.toml"
-    };
+    match args.get(1).map(|s| s.as_str()) {
+        Some("simulate") => simulate(&args[2..]),
+        Some("tune") => tune_parameters(&args[2..]),
+        Some("tournament") => tournament(&args[2..]),
+        Some("replay") => replay(&args[2..]),
+        Some("export") => export(&args[2..]),
+        Some(config_file) => run_bot(config_file),
+        None => run_bot("config.toml"),
+    }
+}
 
+fn run_bot(config_file: &str) {
     let config_string = fs::read_to_string(config_file).unwrap();
-    let config: Config = toml::from_str(&config_string).unwrap();
-    let mut rng = rand::thread_rng();
+    let config: Config = toml::from_str(&config_string).unwrap_or_else(|e| {
+        eprintln!("Invalid config file {}: {}", config_file, e);
+        exit(1);
+    });
+    let mut strategy = gpn21_tron::create_strategy(&config.algorithm).unwrap_or_else(|e| {
+        eprintln!("Invalid config: {}", e);
+        exit(1);
+    });
+    let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    info!("Seed: {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
     loop {
         let mut stream = gpn21_tron::get_connection(&config.server);
         let mut reader = BufReader::new(stream.try_clone().unwrap());
-        let res = gpn21_tron::game_loop(&config, &mut stream, &mut reader, &mut rng);
+        let res = gpn21_tron::game_loop(
+            &config,
+            &mut stream,
+            &mut reader,
+            &mut rng,
+            strategy.as_mut(),
+        );
         if let Err(e) = res {
             error!("IO error: {}", e);
         }
     }
 }
+
+fn simulate(args: &[String]) {
+    let (options, args): (Vec<&String>, Vec<&String>) =
+        args.iter().partition(|a| a.starts_with("--"));
+    if args.len() < 3 {
+        eprintln!(
+            "Usage: simulate <number of games> <algorithm> <algorithm> [<algorithm> ...] \
+             [--seed=<n>]"
+        );
+        return;
+    }
+    let mut config = SimulationConfig {
+        games: args[0].parse().expect("Invalid number of games"),
+        algorithms: args[1..].iter().map(|a| a.to_string()).collect(),
+        size: None,
+        seed: None,
+    };
+    for option in options {
+        match option.split_once('=') {
+            Some(("--seed", seed)) => {
+                config.seed = Some(seed.parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid seed {}", seed);
+                    exit(1);
+                }))
+            }
+            _ => {
+                eprintln!("Unknown option {}", option);
+                exit(1);
+            }
+        }
+    }
+
+    let start = Instant::now();
+    let result = run_simulation(&config).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+    println!(
+        "Played {} games in {:.1}s ({} draws)",
+        result.games,
+        start.elapsed().as_secs_f32(),
+        result.draws
+    );
+    println!("algorithm  | bots | wins | win rate | avg. ticks survived");
+    for (algorithm, stats) in result.variants.iter() {
+        println!(
+            "{:<10} | {:>4} | {:>4} | {:>7.1}% | {:>8.1}",
+            algorithm,
+            stats.seats,
+            stats.wins,
+            stats.win_rate() * 100.0,
+            stats.average_survival()
+        );
+    }
+}
+
+fn tune_parameters(args: &[String]) {
+    let (options, args): (Vec<&String>, Vec<&String>) =
+        args.iter().partition(|a| a.starts_with("--"));
+    if args.len() < 2 {
+        eprintln!(
+            "Usage: tune <algorithm> <iterations> [<opponent> ...] [--games=<n>] \
+             [--evaluation-games=<n>] [--seed=<n>] [--checkpoint=<file>] [--config=<file>]"
+        );
+        return;
+    }
+    let mut config = TuningConfig {
+        strategy: args[0].clone(),
+        base: AlgorithmConfig::default(),
+        iterations: args[1].parse().expect("Invalid number of iterations"),
+        games: 20,
+        opponents: args[2..].iter().map(|a| a.to_string()).collect(),
+        evaluation_games: 200,
+        seed: 0,
+        checkpoint: None,
+    };
+    for option in options {
+        let parse_number = |value: &str| {
+            value.parse().unwrap_or_else(|_| {
+                eprintln!("Invalid number in {}", option);
+                exit(1);
+            })
+        };
+        match option.split_once('=') {
+            Some(("--games", value)) => config.games = parse_number(value) as usize,
+            Some(("--evaluation-games", value)) => {
+                config.evaluation_games = parse_number(value) as usize
+            }
+            Some(("--seed", value)) => config.seed = parse_number(value),
+            Some(("--checkpoint", path)) => config.checkpoint = Some(path.into()),
+            Some(("--config", path)) => {
+                let config_string = fs::read_to_string(path).unwrap();
+                let bot_config: Config = toml::from_str(&config_string).unwrap_or_else(|e| {
+                    eprintln!("Invalid config file {}: {}", path, e);
+                    exit(1);
+                });
+                config.base = bot_config.algorithm;
+            }
+            _ => {
+                eprintln!("Unknown option {}", option);
+                exit(1);
+            }
+        }
+    }
+
+    let start = Instant::now();
+    let result = tune(&config, |progress| {
+        let parameters: Vec<String> = progress
+            .parameters
+            .iter()
+            .map(|(name, value)| format!("{}={:.3}", name, value))
+            .collect();
+        println!(
+            "Iteration {:>4}: score difference {:+.3}, {}",
+            progress.iteration,
+            progress.score_difference,
+            parameters.join(" ")
+        );
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+    println!(
+        "Tuned in {:.1}s. In {} games against the starting parameters:",
+        start.elapsed().as_secs_f32(),
+        result.evaluation_games
+    );
+    println!(
+        "tuned    {:>4} wins, score {:.3}",
+        result.tuned_wins, result.tuned_score
+    );
+    println!(
+        "starting {:>4} wins, score {:.3}",
+        result.base_wins, result.base_score
+    );
+    println!();
+    print!("{}", result.config_section());
+}
+
+fn tournament(args: &[String]) {
+    let (options, args): (Vec<&String>, Vec<&String>) =
+        args.iter().partition(|a| a.starts_with("--"));
+    if args.len() != 1 {
+        eprintln!("Usage: tournament <tournament file> [--output=<file name without extension>]");
+        return;
+    }
+    let mut output = None;
+    for option in options {
+        match option.split_once('=') {
+            Some(("--output", path)) => output = Some(path),
+            _ => {
+                eprintln!("Unknown option {}", option);
+                exit(1);
+            }
+        }
+    }
+    let config_string = fs::read_to_string(args[0]).unwrap();
+    let mut config: TournamentConfig = toml::from_str(&config_string).unwrap_or_else(|e| {
+        eprintln!("Invalid tournament file {}: {}", args[0], e);
+        exit(1);
+    });
+    if config.seed.is_none() {
+        let seed = rand::thread_rng().gen();
+        println!("Seed: {}", seed);
+        config.seed = Some(seed);
+    }
+
+    let start = Instant::now();
+    let result = run_tournament(&config, |round| {
+        println!("Round {} of {} finished", round, config.rounds);
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+    println!(
+        "Played {} games in {:.1}s",
+        result.games,
+        start.elapsed().as_secs_f32()
+    );
+    println!();
+    print!("{}", result.to_markdown());
+    if let Some(output) = output {
+        for (extension, content) in [("csv", result.to_csv()), ("md", result.to_markdown())] {
+            let path = format!("{}.{}", output, extension);
+            fs::write(&path, content).unwrap_or_else(|e| {
+                eprintln!("Could not write {}: {}", path, e);
+                exit(1);
+            });
+        }
+    }
+}
+
+fn replay(args: &[String]) {
+    let (options, args): (Vec<&String>, Vec<&String>) =
+        args.iter().partition(|a| a.starts_with("--"));
+    if args.is_empty() {
+        eprintln!("Usage: replay <recording file> [<algorithm>] [--render[=<overlay>]]");
+        return;
+    }
+    let algorithm = args.get(1).map(|a| a.as_str());
+    let mut render = None;
+    for option in options {
+        match option.split_once('=') {
+            None if option == "--render" => render = Some(Overlay::None),
+            Some(("--render", overlay)) => {
+                render = Some(overlay.parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    exit(1);
+                }))
+            }
+            _ => {
+                eprintln!("Unknown option {}", option);
+                exit(1);
+            }
+        }
+    }
+    let ticks =
+        gpn21_tron::replay::replay(Path::new(&args[0]), algorithm, render).unwrap_or_else(|e| {
+            eprintln!("Could not replay {}: {}", args[0], e);
+            exit(1);
+        });
+
+    let none = "-".to_owned();
+    for tick in ticks.iter().filter(|t| t.diverges()) {
+        if let Some(board) = tick.board.as_ref() {
+            print!("{}", board);
+        }
+        println!(
+            "Tick {:>4}: sent {:<12} replayed {}",
+            tick.tick,
+            tick.sent.as_ref().unwrap_or(&none),
+            tick.decided.as_ref().unwrap_or(&none)
+        );
+        for candidate in tick.candidates.iter() {
+            println!("           {}", candidate);
+        }
+    }
+    println!(
+        "{} of {} ticks diverge from the recorded game",
+        ticks.iter().filter(|t| t.diverges()).count(),
+        ticks.len()
+    );
+}
+
+fn export(args: &[String]) {
+    let (options, args): (Vec<&String>, Vec<&String>) =
+        args.iter().partition(|a| a.starts_with("--"));
+    if args.len() != 2 {
+        eprintln!("Usage: export <recording file> <output directory> [--svg] [--png] [--gif]");
+        return;
+    }
+    let mut export_options = ExportOptions::default();
+    if !options.is_empty() {
+        export_options.gif = false;
+    }
+    for option in options {
+        match option.as_str() {
+            "--svg" => export_options.svg = true,
+            "--png" => export_options.png = true,
+            "--gif" => export_options.gif = true,
+            _ => {
+                eprintln!("Unknown option {}", option);
+                exit(1);
+            }
+        }
+    }
+    match gpn21_tron::export::export(Path::new(&args[0]), Path::new(&args[1]), &export_options) {
+        Ok(frames) => println!("Exported {} frames to {}", frames, args[1]),
+        Err(e) => {
+            eprintln!("Could not export {}: {}", args[0], e);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Statistics about the other bots by name, kept across games and restarts in a JSON file.
+//!
+//! The behaviour models fitted during earlier games serve as prior for the model of a known bot,
+//! so it is recognised from the first tick of a game.
+
+use crate::algorithm::opponent::OpponentModel;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Maximum number of observed moves the prior from earlier games is worth. Keeps the prior from
+/// outweighing the moves of the current game for bots we have seen a lot.
+const PRIOR_MOVES: f32 = 20.0;
+
+#[derive(Deserialize)]
+pub struct ProfilesConfig {
+    /// JSON file to keep the profiles in, created if it doesn't exist
+    pub path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Profile {
+    pub games: u32,
+    /// Ticks survived over all games
+    pub ticks: u64,
+    /// Observed moves over all games. Fewer than the ticks, e.g. for the first tick of a game.
+    pub moves: u64,
+    /// Log-likelihood of all observed moves under each behaviour
+    pub log_likelihood: [f32; 4],
+    /// Number of moves in which the bot could have moved next to another head
+    pub head_on_chances: u64,
+    /// Number of moves in which the bot moved next to another head
+    pub head_on_taken: u64,
+}
+
+impl Profile {
+    pub fn average_survival(&self) -> f32 {
+        if self.games == 0 {
+            0.0
+        } else {
+            self.ticks as f32 / self.games as f32
+        }
+    }
+
+    /// Share of the chances to move next to another head which the bot took
+    pub fn aggressiveness(&self) -> Option<f32> {
+        if self.head_on_chances == 0 {
+            None
+        } else {
+            Some(self.head_on_taken as f32 / self.head_on_chances as f32)
+        }
+    }
+
+    /// Prior for the behaviour model in a new game, see [State::set_behaviour_prior]
+    ///
+    /// [State::set_behaviour_prior]: crate::algorithm::State::set_behaviour_prior
+    pub fn behaviour_prior(&self) -> [f32; 4] {
+        if self.moves == 0 {
+            return [0.0; 4];
+        }
+        let weight = PRIOR_MOVES.min(self.moves as f32) / self.moves as f32;
+        self.log_likelihood.map(|l| l * weight)
+    }
+
+    fn add_game(&mut self, model: &OpponentModel, ticks: u32) {
+        self.games += 1;
+        self.ticks += ticks as u64;
+        self.moves += model.history.len() as u64;
+        for (total, l) in self.log_likelihood.iter_mut().zip(model.log_likelihood) {
+            *total += l;
+        }
+        self.head_on_chances += model.head_on_chances as u64;
+        self.head_on_taken += model.head_on_taken as u64;
+    }
+}
+
+/// The profiles of all bots we have played against, by name
+pub struct Profiles {
+    path: PathBuf,
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl Profiles {
+    /// Read the profiles from the configured file. A missing or broken file is logged and
+    /// replaced on the next save.
+    pub fn load(config: &ProfilesConfig) -> Self {
+        let profiles = match fs::read_to_string(&config.path) {
+            Ok(content) => match serde_json::from_str(&content) {
+                Ok(profiles) => profiles,
+                Err(e) => {
+                    warn!(
+                        "Ignoring broken profiles in {}: {}",
+                        config.path.display(),
+                        e
+                    );
+                    BTreeMap::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                warn!(
+                    "Could not read profiles from {}: {}",
+                    config.path.display(),
+                    e
+                );
+                BTreeMap::new()
+            }
+        };
+        Profiles {
+            path: config.path.clone(),
+            profiles,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.get(name)
+    }
+
+    /// Add what we learned about a bot in a game in which it survived `ticks` ticks.
+    pub fn record_game(&mut self, name: &str, model: &OpponentModel, ticks: u32) {
+        let profile = self.profiles.entry(name.to_owned()).or_default();
+        profile.add_game(model, ticks);
+        info!(
+            "Profile of {}: {} games, {:.1} ticks survived on average",
+            name,
+            profile.games,
+            profile.average_survival()
+        );
+    }
+
+    /// Write the profiles to the file. Errors are logged and do not interrupt the game.
+    pub fn save(&self) {
+        let result = serde_json::to_string_pretty(&self.profiles)
+            .map_err(io::Error::from)
+            .and_then(|content| fs::write(&self.path, content));
+        if let Err(e) = result {
+            warn!("Could not save profiles to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MoveDirection;
+
+    fn config(name: &str) -> ProfilesConfig {
+        ProfilesConfig {
+            path: std::env::temp_dir().join(format!(
+                "gpn21-tron-profiles-{}-{}.json",
+                name,
+                std::process::id()
+            )),
+        }
+    }
+
+    fn model(moves: usize) -> OpponentModel {
+        let mut model = OpponentModel::default();
+        model.history = vec![MoveDirection::Up; moves];
+        model.log_likelihood = [-1.0, -2.0, -3.0, -4.0];
+        model.head_on_chances = 2;
+        model.head_on_taken = 1;
+        model
+    }
+
+    #[test]
+    fn record_game_adds_up_the_games() {
+        let mut profiles = Profiles::load(&config("record"));
+        profiles.record_game("bot", &model(5), 6);
+        // Died before its first observed move
+        profiles.record_game("bot", &OpponentModel::default(), 1);
+        let profile = profiles.get("bot").unwrap();
+        assert_eq!(profile.games, 2);
+        assert_eq!(profile.ticks, 7);
+        assert_eq!(profile.moves, 5);
+        assert_eq!(profile.average_survival(), 3.5);
+        assert_eq!(profile.log_likelihood, [-1.0, -2.0, -3.0, -4.0]);
+        assert_eq!(profile.aggressiveness(), Some(0.5));
+        assert!(profiles.get("other").is_none());
+    }
+
+    #[test]
+    fn behaviour_prior_is_capped() {
+        let profile = |moves| Profile {
+            moves,
+            log_likelihood: [-10.0, -20.0, -30.0, -40.0],
+            ..Profile::default()
+        };
+        assert_eq!(Profile::default().behaviour_prior(), [0.0; 4]);
+        assert_eq!(profile(10).behaviour_prior(), [-10.0, -20.0, -30.0, -40.0]);
+        // Worth as much as PRIOR_MOVES moves
+        assert_eq!(profile(40).behaviour_prior(), [-5.0, -10.0, -15.0, -20.0]);
+    }
+
+    #[test]
+    fn profiles_survive_save_and_load() {
+        let config = config("round-trip");
+        let mut profiles = Profiles::load(&config);
+        profiles.record_game("bot", &model(3), 4);
+        profiles.save();
+        let loaded = Profiles::load(&config);
+        fs::remove_file(&config.path).unwrap();
+
+        let profile = loaded.get("bot").unwrap();
+        assert_eq!(profile.games, 1);
+        assert_eq!(profile.ticks, 4);
+        assert_eq!(profile.moves, 3);
+        assert_eq!(profile.log_likelihood, [-1.0, -2.0, -3.0, -4.0]);
+        assert_eq!((profile.head_on_chances, profile.head_on_taken), (2, 1));
+    }
+
+    #[test]
+    fn broken_file_is_ignored_and_replaced() {
+        let config = config("broken");
+        fs::write(&config.path, "{\"bot\": ").unwrap();
+        let mut profiles = Profiles::load(&config);
+        assert!(profiles.get("bot").is_none());
+        profiles.record_game("bot", &model(1), 1);
+        profiles.save();
+        let loaded = Profiles::load(&config);
+        fs::remove_file(&config.path).unwrap();
+        assert_eq!(loaded.get("bot").unwrap().games, 1);
+    }
+}
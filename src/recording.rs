@@ -0,0 +1,149 @@
+//! Recording of played games to newline-delimited JSON files for later analysis.
+
+use crate::client::PlayerId;
+use crate::AlgorithmConfig;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize)]
+pub struct RecordingConfig {
+    /// Directory to write one file per game into
+    pub directory: PathBuf,
+}
+
+/// One line of a recording file
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Record {
+    /// First record of every file, describing the bot which played the game
+    Header {
+        time: u64,
+        user: String,
+        algorithm: Box<AlgorithmConfig>,
+        /// Seed of the random choices of the strategy in this game, missing in older recordings
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+    /// A raw line received from the server
+    Received { time: u64, line: String },
+    /// A raw command line sent to the server
+    Sent { time: u64, line: String },
+    /// Prior of the behaviour model of an opponent, set from its profile after the previous line
+    Prior {
+        time: u64,
+        player: u32,
+        prior: [f32; 4],
+    },
+}
+
+/// Milliseconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Writes all lines exchanged with the server into a new file for every game.
+///
+/// Lines before the first `game` message of a connection are not recorded. Errors while writing
+/// are logged and do not interrupt the game.
+pub struct Recorder {
+    directory: PathBuf,
+    user: String,
+    algorithm: AlgorithmConfig,
+    file: Option<BufWriter<File>>,
+}
+
+impl Recorder {
+    pub fn new(config: &RecordingConfig, user: &str, algorithm: &AlgorithmConfig) -> Self {
+        Recorder {
+            directory: config.directory.clone(),
+            user: user.to_owned(),
+            algorithm: algorithm.clone(),
+            file: None,
+        }
+    }
+
+    /// Close the current recording file (if any) and start a new one for a game played with the
+    /// given seed.
+    pub fn start_game(&mut self, seed: u64) {
+        self.file = None;
+        let time = now();
+        let user: String = self
+            .user
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = self
+            .directory
+            .join(format!("game-{}-{}.ndjson", time, user));
+        match Self::create_file(&path) {
+            Ok(file) => {
+                info!("Recording game to {}", path.display());
+                self.file = Some(file);
+                self.write(&Record::Header {
+                    time,
+                    user: self.user.clone(),
+                    algorithm: Box::new(self.algorithm.clone()),
+                    seed: Some(seed),
+                });
+            }
+            Err(e) => warn!("Could not create recording {}: {}", path.display(), e),
+        }
+    }
+
+    fn create_file(path: &Path) -> io::Result<BufWriter<File>> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        Ok(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn received(&mut self, line: &str) {
+        self.write(&Record::Received {
+            time: now(),
+            line: line.trim_end().to_owned(),
+        });
+    }
+
+    pub fn sent(&mut self, line: &str) {
+        self.write(&Record::Sent {
+            time: now(),
+            line: line.trim_end().to_owned(),
+        });
+        self.flush();
+    }
+
+    pub fn prior(&mut self, player: PlayerId, prior: [f32; 4]) {
+        self.write(&Record::Prior {
+            time: now(),
+            player: player.0,
+            prior,
+        });
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(file) = self.file.as_mut() {
+            if let Err(e) = file.flush() {
+                warn!("Could not write recording: {}", e);
+            }
+        }
+    }
+
+    fn write(&mut self, record: &Record) {
+        if let Some(file) = self.file.as_mut() {
+            let result = serde_json::to_writer(&mut *file, record)
+                .map_err(io::Error::from)
+                .and_then(|_| file.write_all(b"\n"));
+            if let Err(e) = result {
+                warn!("Could not write recording: {}", e);
+                self.file = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Re-running strategies on recorded games.
+//!
+//! The lines of a recording are fed into a fresh [State] like in a live game. At every tick, the
+//! chosen strategy is asked for its decision, which is compared to the command actually sent in the
+//! recorded game. The random choices of the strategy are seeded like in the recorded game, and the
+//! behaviour priors of known opponents are set like in the game, so the strategy of the recording
+//! decides exactly like in the game unless it ran out of time there.
+
+use crate::algorithm::render::render_ansi;
+pub use crate::algorithm::render::Overlay;
+use crate::algorithm::{create_strategy, decide_action, CandidateScore, Deadline, State, Strategy};
+use crate::client::{format_command, parse_answer, Answer, PlayerId};
+use crate::recording::Record;
+use crate::{AlgorithmConfig, Position};
+use log::warn;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Decision of the replayed strategy in a single tick, compared to the recorded game
+pub struct TickComparison {
+    /// Number of the tick within the game, starting at 1
+    pub tick: usize,
+    /// Command line sent in the recorded game, if any
+    pub sent: Option<String>,
+    /// Command line the replayed strategy would have sent, if any
+    pub decided: Option<String>,
+    /// Scores of the candidate directions of the replayed strategy, best first
+    pub candidates: Vec<CandidateScore>,
+    /// The board at this tick, if rendering was requested
+    pub board: Option<String>,
+}
+
+impl TickComparison {
+    pub fn diverges(&self) -> bool {
+        self.sent != self.decided
+    }
+}
+
+/// Replay the recording at `path`. If no algorithm is given, the one from the recording header is
+/// used. If an overlay is given, the board is rendered at every tick.
+pub fn replay(
+    path: &Path,
+    algorithm: Option<&str>,
+    render: Option<Overlay>,
+) -> Result<Vec<TickComparison>, Box<dyn Error>> {
+    let mut ticks: Vec<TickComparison> = Vec::new();
+    for_each_tick(path, algorithm, |recorded| {
+        let board = render.map(|overlay| render_ansi(recorded.state, overlay, recorded.config));
+        let decision = decide_action(
+            recorded.state,
+            recorded.rng,
+            recorded.strategy,
+            &Deadline::none(),
+        );
+        ticks.push(TickComparison {
+            tick: recorded.tick,
+            sent: recorded.sent.map(|s| s.to_owned()),
+            decided: decision
+                .command()
+                .map(|command| format_command(&command).trim_end().to_owned()),
+            candidates: decision.candidates,
+            board,
+        });
+    })?;
+    Ok(ticks)
+}
+
+/// A tick of a recorded game
+pub(crate) struct RecordedTick<'a> {
+    /// Number of the tick within the game, starting at 1
+    pub tick: usize,
+    /// The state when the tick was received
+    pub state: &'a mut State,
+    pub strategy: &'a mut dyn Strategy,
+    /// Configuration the strategy was created from
+    pub config: &'a AlgorithmConfig,
+    /// Random number generator for the strategy, seeded like in the recorded game
+    pub rng: &'a mut StdRng,
+    /// Command line sent in the recorded game in response to this tick
+    pub sent: Option<&'a str>,
+    /// Players which died since the previous tick, with the position of their last head
+    pub deaths: &'a [(PlayerId, Position)],
+}
+
+fn load_records(path: &Path) -> Result<Vec<Record>, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    for line in reader.lines() {
+        records.push(serde_json::from_str(&line?)?);
+    }
+    Ok(records)
+}
+
+/// Feed the recording at `path` into a fresh state and call `on_tick` for every tick. The strategy
+/// hooks are called like in a live game.
+pub(crate) fn for_each_tick(
+    path: &Path,
+    algorithm: Option<&str>,
+    mut on_tick: impl FnMut(RecordedTick<'_>),
+) -> Result<(), Box<dyn Error>> {
+    let records = load_records(path)?;
+    let mut state = State::default();
+    let mut strategy = None;
+    let mut config = AlgorithmConfig::default();
+    let mut rng = StdRng::from_entropy();
+    let mut tick = 0;
+    let mut deaths = Vec::new();
+
+    for (i, record) in records.iter().enumerate() {
+        match record {
+            Record::Header {
+                algorithm: recorded,
+                seed,
+                ..
+            } => {
+                match seed {
+                    Some(seed) => rng = StdRng::seed_from_u64(*seed),
+                    None => warn!("Recording has no seed, random choices differ from the game"),
+                }
+                config = match algorithm {
+                    Some(name) => AlgorithmConfig {
+                        algorithm: name.to_owned(),
+                        ..AlgorithmConfig::default()
+                    },
+                    None => recorded.as_ref().clone(),
+                };
+                strategy = Some(create_strategy(&config)?);
+            }
+            Record::Sent { .. } => {}
+            Record::Prior { player, prior, .. } => {
+                state.set_behaviour_prior(PlayerId(*player), *prior);
+            }
+            Record::Received { line, .. } => {
+                let answer = match parse_answer(line) {
+                    Ok(answer) => answer,
+                    Err(e) => {
+                        warn!("Skipping line from recording: {}", e);
+                        continue;
+                    }
+                };
+                let strategy = strategy
+                    .as_mut()
+                    .ok_or("Recording does not start with a header")?;
+                if let Answer::Die(players) = &answer {
+                    deaths.extend(players.iter().filter_map(|player| {
+                        state
+                            .player_head(*player)
+                            .map(|head| (*player, head.clone()))
+                    }));
+                    if players.contains(&state.my_id()) {
+                        strategy.on_death(&state);
+                    }
+                }
+                state.update_from_answer(&answer);
+                match &answer {
+                    Answer::Game(_, _) => strategy.init(&state),
+                    Answer::Tick => {
+                        tick += 1;
+                        on_tick(RecordedTick {
+                            tick,
+                            state: &mut state,
+                            strategy: strategy.as_mut(),
+                            config: &config,
+                            rng: &mut rng,
+                            sent: sent_after(&records[i + 1..]),
+                            deaths: &deaths,
+                        });
+                        deaths.clear();
+                    }
+                    Answer::Win(_, _) => strategy.on_game_end(&state, true),
+                    Answer::Lose(_, _) => strategy.on_game_end(&state, false),
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The first command sent before the next tick
+fn sent_after(records: &[Record]) -> Option<&str> {
+    for record in records {
+        match record {
+            Record::Sent { line, .. } => return Some(line),
+            Record::Received { line, .. } if line.trim() == "tick" => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::MctsConfig;
+    use crate::client::{format_answer, Command};
+    use crate::server::game::Game;
+    use std::fs;
+
+    fn received(answer: &Answer) -> Record {
+        Record::Received {
+            time: 0,
+            line: format_answer(answer).trim_end().to_owned(),
+        }
+    }
+
+    /// Play a game between two bots with `config` and record it from the view of the first bot,
+    /// like the bot does in a live game
+    fn record_game(config: &AlgorithmConfig, seed: u64) -> Vec<Record> {
+        let players = [PlayerId(0), PlayerId(1)];
+        let size = Position { x: 10, y: 10 };
+        let mut game = Game::new(size.clone(), &players, &mut StdRng::seed_from_u64(seed));
+        let mut bots: Vec<(State, Box<dyn Strategy>, StdRng)> = players
+            .iter()
+            .map(|player| {
+                let mut state = State::default();
+                state.update_from_answer(&Answer::Game(size.clone(), *player));
+                let mut strategy = create_strategy(config).unwrap();
+                strategy.init(&state);
+                (
+                    state,
+                    strategy,
+                    StdRng::seed_from_u64(seed + player.0 as u64),
+                )
+            })
+            .collect();
+        let mut records = vec![
+            Record::Header {
+                time: 0,
+                user: "test".to_owned(),
+                algorithm: Box::new(config.clone()),
+                seed: Some(seed),
+            },
+            received(&Answer::Game(size, players[0])),
+        ];
+
+        let mut answers: Vec<Answer> = players
+            .iter()
+            .map(|p| Answer::Pos(*p, game.heads()[p].clone()))
+            .collect();
+        while game.alive_players().len() > 1 {
+            answers.push(Answer::Tick);
+            records.extend(answers.iter().map(received));
+            for (player, (state, strategy, rng)) in players.iter().zip(bots.iter_mut()) {
+                for answer in answers.iter() {
+                    state.update_from_answer(answer);
+                }
+                if !game.is_alive(*player) {
+                    continue;
+                }
+                let decision = decide_action(state, rng, strategy.as_mut(), &Deadline::none());
+                if let Some(command) = decision.command() {
+                    if *player == players[0] {
+                        records.push(Record::Sent {
+                            time: 0,
+                            line: format_command(&command).trim_end().to_owned(),
+                        });
+                    }
+                    if let Command::Move(direction) = command {
+                        game.set_direction(*player, direction);
+                    }
+                }
+            }
+            answers.clear();
+            let dead = game.step();
+            if !dead.is_empty() {
+                answers.push(Answer::Die(dead));
+            }
+            answers.extend(
+                game.alive_players()
+                    .iter()
+                    .map(|p| Answer::Pos(*p, game.heads()[p].clone())),
+            );
+        }
+        records
+    }
+
+    #[test]
+    fn recorded_game_replays_without_divergence() {
+        // The random playouts make every decision depend on the seed
+        let config = AlgorithmConfig {
+            algorithm: "mcts".to_owned(),
+            mcts: MctsConfig {
+                iterations: 200,
+                ..MctsConfig::default()
+            },
+            ..AlgorithmConfig::default()
+        };
+        let path = std::env::temp_dir().join(format!(
+            "gpn21-tron-replay-test-{}.ndjson",
+            std::process::id()
+        ));
+        let lines: Vec<String> = record_game(&config, 3)
+            .iter()
+            .map(|record| serde_json::to_string(record).unwrap())
+            .collect();
+        fs::write(&path, lines.join("\n")).unwrap();
+        let ticks = replay(&path, None, None);
+        fs::remove_file(&path).unwrap();
+
+        let ticks = ticks.unwrap();
+        assert!(ticks.len() > 1);
+        for tick in ticks.iter() {
+            assert!(!tick.diverges(), "Tick {} diverges", tick.tick);
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! A local gpn-tron game server for offline matches between bots.
+//!
+//! The server speaks the same line protocol as the GPN server, so any number of bot processes can
+//! connect to it on localhost.
+
+use crate::client::{format_answer, parse_command, Answer, Command, PlayerId};
+use crate::Position;
+use game::Game;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use std::{io, thread};
+
+pub(crate) mod game;
+
+#[derive(Deserialize)]
+pub struct GameServerConfig {
+    #[serde(default = "default_listen_address")]
+    pub address: String,
+    #[serde(default = "default_min_players")]
+    pub min_players: usize,
+    #[serde(default = "default_tick_interval")]
+    pub tick_interval_ms: u64,
+    /// Fixed board width. If not given, the board size scales with the number of players.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    #[serde(default = "default_motd")]
+    pub motd: String,
+}
+
+fn default_listen_address() -> String {
+    "127.0.0.1:4000".to_owned()
+}
+
+fn default_min_players() -> usize {
+    2
+}
+
+fn default_tick_interval() -> u64 {
+    200
+}
+
+fn default_motd() -> String {
+    "Welcome to the local gpn-tron server".to_owned()
+}
+
+impl Default for GameServerConfig {
+    fn default() -> Self {
+        GameServerConfig {
+            address: default_listen_address(),
+            min_players: default_min_players(),
+            tick_interval_ms: default_tick_interval(),
+            width: None,
+            height: None,
+            motd: default_motd(),
+        }
+    }
+}
+
+type ConnectionId = usize;
+
+enum Event {
+    Connected(ConnectionId, TcpStream),
+    Line(ConnectionId, String),
+    Disconnected(ConnectionId),
+}
+
+struct Connection {
+    stream: TcpStream,
+    user: Option<String>,
+}
+
+#[derive(Default)]
+struct UserRecord {
+    password: String,
+    wins: u32,
+    losses: u32,
+}
+
+struct RunningGame {
+    game: Game,
+    players: HashMap<PlayerId, ConnectionId>,
+    moved: HashSet<PlayerId>,
+    next_tick: Instant,
+}
+
+/// Accept connections on the configured address and run games forever.
+pub fn run(config: &GameServerConfig) -> io::Result<()> {
+    let listener = TcpListener::bind(&config.address)?;
+    info!("Listening on {}", config.address);
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || accept_connections(listener, sender));
+    Server::new(config).run(receiver);
+    Ok(())
+}
+
+fn accept_connections(listener: TcpListener, events: mpsc::Sender<Event>) {
+    for (connection_id, stream) in listener.incoming().enumerate() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Could not accept connection: {}", e);
+                continue;
+            }
+        };
+        let reader = match stream.try_clone() {
+            Ok(s) => BufReader::new(s),
+            Err(e) => {
+                warn!("Could not clone connection: {}", e);
+                continue;
+            }
+        };
+        if events
+            .send(Event::Connected(connection_id, stream))
+            .is_err()
+        {
+            return;
+        }
+        let events = events.clone();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                match line {
+                    Ok(line) => {
+                        if events.send(Event::Line(connection_id, line)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+            let _ = events.send(Event::Disconnected(connection_id));
+        });
+    }
+}
+
+struct Server<'a> {
+    config: &'a GameServerConfig,
+    connections: HashMap<ConnectionId, Connection>,
+    users: HashMap<String, UserRecord>,
+    game: Option<RunningGame>,
+    rng: rand::rngs::ThreadRng,
+}
+
+impl<'a> Server<'a> {
+    fn new(config: &'a GameServerConfig) -> Self {
+        Server {
+            config,
+            connections: HashMap::new(),
+            users: HashMap::new(),
+            game: None,
+            rng: rand::thread_rng(),
+        }
+    }
+
+    fn run(mut self, events: mpsc::Receiver<Event>) {
+        loop {
+            let event = match &self.game {
+                Some(game) => {
+                    let timeout = game.next_tick.saturating_duration_since(Instant::now());
+                    match events.recv_timeout(timeout) {
+                        Ok(event) => Some(event),
+                        Err(mpsc::RecvTimeoutError::Timeout) => None,
+                        Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                None => match events.recv() {
+                    Ok(event) => Some(event),
+                    Err(_) => return,
+                },
+            };
+            if let Some(event) = event {
+                self.handle_event(event);
+            }
+            let tick_due = self.game.as_ref().is_some_and(|game| {
+                Instant::now() >= game.next_tick
+                    || game.game.alive_players().len() == game.moved.len()
+            });
+            if tick_due {
+                self.step_game();
+            }
+            if self.game.is_none() {
+                self.try_start_game();
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Connected(id, stream) => {
+                info!("Client {} connected", id);
+                self.connections
+                    .insert(id, Connection { stream, user: None });
+                self.send(id, &Answer::Motd(self.config.motd.clone()));
+            }
+            Event::Line(id, line) => {
+                debug!("Received from client {}: {}", id, line);
+                self.handle_command(id, &line);
+            }
+            Event::Disconnected(id) => {
+                info!("Client {} disconnected", id);
+                self.connections.remove(&id);
+                if let Some(player) = self.player_of_connection(id) {
+                    let running = self.game.as_mut().unwrap();
+                    running.game.remove_player(player);
+                    running.players.remove(&player);
+                    let alive = running.game.alive_players();
+                    self.broadcast(&Answer::Die(vec![player]));
+                    if alive.len() <= 1 {
+                        self.end_game(alive.first().copied());
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_command(&mut self, id: ConnectionId, line: &str) {
+        match parse_command(line) {
+            Some(Command::Join(user, password)) => self.join(id, user, password),
+            Some(Command::Move(direction)) => {
+                if let Some(player) = self.player_of_connection(id) {
+                    let running = self.game.as_mut().unwrap();
+                    running.game.set_direction(player, direction);
+                    running.moved.insert(player);
+                }
+            }
+            Some(Command::Chat(msg)) => {
+                if let Some(player) = self.player_of_connection(id) {
+                    self.broadcast(&Answer::Message(player, msg.to_owned()));
+                }
+            }
+            None => self.send(id, &Answer::Error("ERROR_INVALID_PACKET".to_owned())),
+        }
+    }
+
+    fn join(&mut self, id: ConnectionId, user: &str, password: &str) {
+        if self.connections[&id].user.is_some() {
+            self.send(id, &Answer::Error("ERROR_ALREADY_JOINED".to_owned()));
+            return;
+        }
+        if user.is_empty() || user.contains('|') {
+            self.send(id, &Answer::Error("ERROR_INVALID_USERNAME".to_owned()));
+            return;
+        }
+        if self
+            .connections
+            .values()
+            .any(|c| c.user.as_deref() == Some(user))
+        {
+            self.send(id, &Answer::Error("ERROR_ALREADY_CONNECTED".to_owned()));
+            return;
+        }
+        let record = self
+            .users
+            .entry(user.to_owned())
+            .or_insert_with(|| UserRecord {
+                password: password.to_owned(),
+                ..Default::default()
+            });
+        if record.password != password {
+            self.send(id, &Answer::Error("ERROR_WRONG_PASSWORD".to_owned()));
+            return;
+        }
+        info!("Client {} joined as {}", id, user);
+        self.connections.get_mut(&id).unwrap().user = Some(user.to_owned());
+    }
+
+    fn player_of_connection(&self, id: ConnectionId) -> Option<PlayerId> {
+        let running = self.game.as_ref()?;
+        running
+            .players
+            .iter()
+            .find(|(player, connection)| **connection == id && running.game.is_alive(**player))
+            .map(|(player, _connection)| *player)
+    }
+
+    fn try_start_game(&mut self) {
+        let mut waiting: Vec<ConnectionId> = self
+            .connections
+            .iter()
+            .filter(|(_id, c)| c.user.is_some())
+            .map(|(id, _c)| *id)
+            .collect();
+        if waiting.len() < self.config.min_players.max(1) {
+            return;
+        }
+        waiting.sort_unstable();
+
+        let num_players = waiting.len() as u32;
+        let default_size = (num_players * 4).max(10);
+        let size = Position {
+            x: self.config.width.unwrap_or(default_size),
+            y: self.config.height.unwrap_or(default_size),
+        };
+        let players: HashMap<PlayerId, ConnectionId> = waiting
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (PlayerId(i as u32), *id))
+            .collect();
+        let mut ids: Vec<PlayerId> = players.keys().copied().collect();
+        ids.sort_by_key(|p| p.0);
+        info!(
+            "Starting game with {} players on a {}x{} board",
+            ids.len(),
+            size.x,
+            size.y
+        );
+
+        let game = Game::new(size.clone(), &ids, &mut self.rng);
+        for (player, connection) in players.iter() {
+            self.send(*connection, &Answer::Game(size.clone(), *player));
+        }
+        for (player, connection) in players.iter() {
+            let name = self.connections[connection].user.clone().unwrap();
+            self.broadcast_to(&players, &Answer::Player(*player, name));
+        }
+        for player in ids.iter() {
+            self.broadcast_to(
+                &players,
+                &Answer::Pos(*player, game.heads()[player].clone()),
+            );
+        }
+        self.broadcast_to(&players, &Answer::Tick);
+        self.game = Some(RunningGame {
+            game,
+            players,
+            moved: HashSet::new(),
+            next_tick: Instant::now() + Duration::from_millis(self.config.tick_interval_ms),
+        });
+    }
+
+    fn step_game(&mut self) {
+        let running = self.game.as_mut().unwrap();
+        let dead = running.game.step();
+        running.moved.clear();
+        running.next_tick = Instant::now() + Duration::from_millis(self.config.tick_interval_ms);
+        let alive = running.game.alive_players();
+        let positions: Vec<Answer> = alive
+            .iter()
+            .map(|player| Answer::Pos(*player, running.game.heads()[player].clone()))
+            .collect();
+
+        if !dead.is_empty() {
+            self.broadcast(&Answer::Die(dead));
+        }
+        for answer in positions.iter() {
+            self.broadcast(answer);
+        }
+        if alive.len() <= 1 {
+            self.end_game(alive.first().copied());
+        } else {
+            self.broadcast(&Answer::Tick);
+        }
+    }
+
+    fn end_game(&mut self, winner: Option<PlayerId>) {
+        let running = self.game.take().unwrap();
+        for (player, connection) in running.players.iter() {
+            let user = match self
+                .connections
+                .get(connection)
+                .and_then(|c| c.user.clone())
+            {
+                Some(user) => user,
+                None => continue,
+            };
+            let record = self.users.get_mut(&user).unwrap();
+            if Some(*player) == winner {
+                record.wins += 1;
+                let answer = Answer::Win(record.wins, record.losses);
+                info!("{} won the game", user);
+                self.send(*connection, &answer);
+            } else {
+                record.losses += 1;
+                let answer = Answer::Lose(record.wins, record.losses);
+                self.send(*connection, &answer);
+            }
+        }
+    }
+
+    fn broadcast(&mut self, answer: &Answer) {
+        if let Some(running) = self.game.as_ref() {
+            let players = running.players.clone();
+            self.broadcast_to(&players, answer);
+        }
+    }
+
+    fn broadcast_to(&mut self, players: &HashMap<PlayerId, ConnectionId>, answer: &Answer) {
+        for connection in players.values() {
+            self.send(*connection, answer);
+        }
+    }
+
+    fn send(&mut self, id: ConnectionId, answer: &Answer) {
+        if let Some(connection) = self.connections.get_mut(&id) {
+            let data = format_answer(answer);
+            if let Err(e) = connection.stream.write_all(data.as_bytes()) {
+                debug!("Could not send to client {}: {}", id, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use crate::algorithm::helper::move_by_direction;
+use crate::client::PlayerId;
+use crate::{MoveDirection, Position};
+use rand::Rng;
+use std::collections::HashMap;
+
+/// The rules of a single gpn-tron game, independent of any networking.
+///
+/// All players move simultaneously on a wrap-around board. A player dies when moving onto an
+/// occupied field or when moving onto the same field as another player in the same step
+/// (head-on collision). The trails of dead players are removed from the board.
+pub struct Game {
+    size: Position,
+    field_occupation: ndarray::Array2<Option<PlayerId>>,
+    heads: HashMap<PlayerId, Position>,
+    directions: HashMap<PlayerId, MoveDirection>,
+}
+
+impl Game {
+    pub fn new<R: Rng>(size: Position, players: &[PlayerId], rng: &mut R) -> Game {
+        assert!(
+            (size.x * size.y) as usize >= players.len(),
+            "Board too small for {} players",
+            players.len()
+        );
+        let mut game = Game {
+            field_occupation: ndarray::Array2::from_elem(size.as_dim(), None),
+            size,
+            heads: HashMap::new(),
+            directions: HashMap::new(),
+        };
+        for player in players {
+            let position = loop {
+                let candidate = Position {
+                    x: rng.gen_range(0..game.size.x),
+                    y: rng.gen_range(0..game.size.y),
+                };
+                if game.field_occupation[candidate.as_dim()].is_none() {
+                    break candidate;
+                }
+            };
+            game.field_occupation[position.as_dim()] = Some(*player);
+            game.heads.insert(*player, position);
+            game.directions.insert(*player, MoveDirection::Up);
+        }
+        game
+    }
+
+    pub fn heads(&self) -> &HashMap<PlayerId, Position> {
+        &self.heads
+    }
+
+    pub fn is_alive(&self, player: PlayerId) -> bool {
+        self.heads.contains_key(&player)
+    }
+
+    /// Ids of all players still alive, in ascending order.
+    pub fn alive_players(&self) -> Vec<PlayerId> {
+        let mut players: Vec<PlayerId> = self.heads.keys().copied().collect();
+        players.sort_by_key(|p| p.0);
+        players
+    }
+
+    /// Set the direction the player will move in the next step. Players who do not send a new
+    /// direction keep moving in their last direction.
+    pub fn set_direction(&mut self, player: PlayerId, direction: MoveDirection) {
+        if self.is_alive(player) {
+            self.directions.insert(player, direction);
+        }
+    }
+
+    /// Remove a player from the game, e.g. because the client disconnected.
+    pub fn remove_player(&mut self, player: PlayerId) {
+        self.remove_players(&[player]);
+    }
+
+    /// Move all players simultaneously and return the players which died in this step.
+    pub fn step(&mut self) -> Vec<PlayerId> {
+        let targets: HashMap<PlayerId, Position> = self
+            .heads
+            .iter()
+            .map(|(player, head)| {
+                (
+                    *player,
+                    move_by_direction(head, &self.directions[player], &self.size),
+                )
+            })
+            .collect();
+
+        let mut dead: Vec<PlayerId> = targets
+            .iter()
+            .filter(|(player, target)| {
+                self.field_occupation[target.as_dim()].is_some()
+                    || targets
+                        .iter()
+                        .any(|(other, other_target)| other != *player && other_target == *target)
+            })
+            .map(|(player, _target)| *player)
+            .collect();
+        dead.sort_by_key(|p| p.0);
+
+        for (player, target) in targets {
+            if !dead.contains(&player) {
+                self.field_occupation[target.as_dim()] = Some(player);
+                self.heads.insert(player, target);
+            }
+        }
+        self.remove_players(&dead);
+        dead
+    }
+
+    fn remove_players(&mut self, players: &[PlayerId]) {
+        for field in self.field_occupation.iter_mut() {
+            if field.is_some_and(|occupied_by| players.contains(&occupied_by)) {
+                *field = None;
+            }
+        }
+        for player in players {
+            self.heads.remove(player);
+            self.directions.remove(player);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: PlayerId = PlayerId(0);
+    const B: PlayerId = PlayerId(1);
+    const C: PlayerId = PlayerId(2);
+
+    fn pos(x: u32, y: u32) -> Position {
+        Position { x, y }
+    }
+
+    /// A game with the players placed at fixed positions instead of random ones.
+    fn game(size: Position, players: &[(PlayerId, Position, MoveDirection)]) -> Game {
+        let mut game = Game {
+            field_occupation: ndarray::Array2::from_elem(size.as_dim(), None),
+            size,
+            heads: HashMap::new(),
+            directions: HashMap::new(),
+        };
+        for (player, position, direction) in players {
+            game.field_occupation[position.as_dim()] = Some(*player);
+            game.heads.insert(*player, position.clone());
+            game.directions.insert(*player, direction.clone());
+        }
+        game
+    }
+
+    #[test]
+    fn moves_players_and_leaves_trails() {
+        let mut game = game(
+            pos(5, 5),
+            &[
+                (A, pos(1, 1), MoveDirection::Right),
+                (B, pos(3, 3), MoveDirection::Down),
+            ],
+        );
+        assert!(game.step().is_empty());
+        assert_eq!(game.heads()[&A], pos(2, 1));
+        assert_eq!(game.heads()[&B], pos(3, 4));
+        assert_eq!(game.field_occupation[pos(1, 1).as_dim()], Some(A));
+        assert_eq!(game.field_occupation[pos(2, 1).as_dim()], Some(A));
+        assert_eq!(game.field_occupation[pos(3, 3).as_dim()], Some(B));
+    }
+
+    #[test]
+    fn keeps_last_direction_until_changed() {
+        let mut game = game(pos(5, 5), &[(A, pos(0, 0), MoveDirection::Right)]);
+        game.step();
+        game.set_direction(A, MoveDirection::Down);
+        game.step();
+        assert_eq!(game.heads()[&A], pos(1, 1));
+    }
+
+    #[test]
+    fn head_on_collision_kills_both_players() {
+        let mut game = game(
+            pos(5, 5),
+            &[
+                (A, pos(1, 2), MoveDirection::Right),
+                (B, pos(3, 2), MoveDirection::Left),
+                (C, pos(0, 4), MoveDirection::Right),
+            ],
+        );
+        assert_eq!(game.step(), vec![A, B]);
+        assert_eq!(game.alive_players(), vec![C]);
+        assert_eq!(game.field_occupation[pos(2, 2).as_dim()], None);
+    }
+
+    #[test]
+    fn moving_into_a_trail_kills_the_player() {
+        let mut game = game(
+            pos(5, 5),
+            &[
+                (A, pos(1, 1), MoveDirection::Right),
+                (B, pos(2, 3), MoveDirection::Up),
+            ],
+        );
+        // A moves onto (2, 1), B onto (2, 2) below it.
+        assert!(game.step().is_empty());
+        assert_eq!(game.step(), vec![B]);
+        assert_eq!(game.alive_players(), vec![A]);
+    }
+
+    #[test]
+    fn moving_into_own_trail_kills_the_player() {
+        let mut game = game(pos(5, 5), &[(A, pos(1, 1), MoveDirection::Right)]);
+        game.step();
+        game.set_direction(A, MoveDirection::Left);
+        assert_eq!(game.step(), vec![A]);
+    }
+
+    #[test]
+    fn removes_trails_of_dead_players() {
+        let mut game = game(
+            pos(5, 5),
+            &[
+                (A, pos(1, 1), MoveDirection::Right),
+                (B, pos(2, 0), MoveDirection::Down),
+            ],
+        );
+        assert_eq!(game.step(), vec![A, B]);
+        assert!(game.field_occupation.iter().all(|field| field.is_none()));
+    }
+
+    #[test]
+    fn wraps_around_the_board_edges() {
+        let mut game = game(
+            pos(4, 3),
+            &[
+                (A, pos(3, 0), MoveDirection::Right),
+                (B, pos(1, 0), MoveDirection::Up),
+            ],
+        );
+        assert!(game.step().is_empty());
+        assert_eq!(game.heads()[&A], pos(0, 0));
+        assert_eq!(game.heads()[&B], pos(1, 2));
+        game.set_direction(A, MoveDirection::Left);
+        game.set_direction(B, MoveDirection::Down);
+        // Both wrap back onto their own trails.
+        assert_eq!(game.step(), vec![A, B]);
+    }
+
+    #[test]
+    fn last_survivor_remains() {
+        let mut game = game(
+            pos(6, 6),
+            &[
+                (A, pos(0, 0), MoveDirection::Down),
+                (B, pos(2, 1), MoveDirection::Left),
+                (C, pos(4, 4), MoveDirection::Right),
+            ],
+        );
+        // A moves to (0, 1) and B to (1, 1), then B runs into A's trail.
+        assert!(game.step().is_empty());
+        assert_eq!(game.step(), vec![B]);
+        assert_eq!(game.alive_players(), vec![A, C]);
+        game.set_direction(C, MoveDirection::Left);
+        assert_eq!(game.step(), vec![C]);
+        assert_eq!(game.alive_players(), vec![A]);
+    }
+
+    #[test]
+    fn removed_players_leave_the_game() {
+        let mut game = game(
+            pos(5, 5),
+            &[
+                (A, pos(0, 0), MoveDirection::Down),
+                (B, pos(3, 3), MoveDirection::Down),
+            ],
+        );
+        game.remove_player(B);
+        assert_eq!(game.alive_players(), vec![A]);
+        assert_eq!(game.field_occupation[pos(3, 3).as_dim()], None);
+        game.set_direction(B, MoveDirection::Up);
+        assert!(game.step().is_empty());
+        assert!(!game.is_alive(B));
+    }
+}
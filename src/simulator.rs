@@ -0,0 +1,254 @@
+//! Headless in-process matches between algorithm variants.
+//!
+//! Every bot gets its own [State] which is fed with the same answers a real server would send, so
+//! the algorithms run exactly like in a networked game, just without any sockets.
+
+use crate::algorithm::{
+    create_strategy, decide_action, Deadline, State, Strategy, UnknownStrategy,
+};
+use crate::client::{Answer, PlayerId};
+use crate::server::game::Game;
+use crate::{AlgorithmConfig, Position};
+use log::debug;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::thread;
+
+pub struct SimulationConfig {
+    pub games: usize,
+    /// Strategy name of each bot taking part in every game.
+    pub algorithms: Vec<String>,
+    /// Board size. If not given, the board size scales with the number of players like on the local
+    /// server.
+    pub size: Option<Position>,
+    /// Seed of the games, see [play_games]. Without a seed, every run differs.
+    pub seed: Option<u64>,
+}
+
+#[derive(Default, Debug)]
+pub struct VariantStats {
+    /// Number of bots of this variant over all games
+    pub seats: usize,
+    pub wins: usize,
+    pub ticks_survived: usize,
+}
+
+impl VariantStats {
+    pub fn win_rate(&self) -> f32 {
+        self.wins as f32 / self.seats as f32
+    }
+
+    pub fn average_survival(&self) -> f32 {
+        self.ticks_survived as f32 / self.seats as f32
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct SimulationResult {
+    pub games: usize,
+    /// Games where the last players died simultaneously
+    pub draws: usize,
+    pub variants: BTreeMap<String, VariantStats>,
+}
+
+impl SimulationResult {
+    fn record(&mut self, algorithms: &[String], outcome: &GameOutcome) {
+        self.games += 1;
+        if outcome.winner.is_none() {
+            self.draws += 1;
+        }
+        for (seat, algorithm) in algorithms.iter().enumerate() {
+            let stats = self.variants.entry(algorithm.clone()).or_default();
+            stats.seats += 1;
+            stats.ticks_survived += outcome.ticks_survived[seat];
+            if outcome.winner == Some(seat) {
+                stats.wins += 1;
+            }
+        }
+    }
+}
+
+pub struct GameOutcome {
+    /// Seed the game was played with, see [simulate_game]
+    pub seed: u64,
+    /// Index of the winning bot, if any
+    pub winner: Option<usize>,
+    /// Number of ticks each bot survived
+    pub ticks_survived: Vec<usize>,
+}
+
+/// Run all games of the simulation, spread over all available CPU cores.
+pub fn run_simulation(config: &SimulationConfig) -> Result<SimulationResult, UnknownStrategy> {
+    let configs = algorithm_configs(&config.algorithms);
+    for config in configs.iter() {
+        create_strategy(config)?;
+    }
+    let size = config
+        .size
+        .clone()
+        .unwrap_or_else(|| default_size(configs.len()));
+
+    let mut result = SimulationResult::default();
+    for outcome in play_games(&configs, &size, config.games, config.seed) {
+        result.record(&config.algorithms, &outcome);
+    }
+    Ok(result)
+}
+
+/// The board size of the local server for the given number of players
+pub fn default_size(players: usize) -> Position {
+    let size = (players as u32 * 4).max(10);
+    Position { x: size, y: size }
+}
+
+/// Play `games` games between the given strategies, spread over all available CPU cores, and
+/// return their outcomes in order. With a seed, game `i` is played with the seed `seed + i`, so every
+/// game is played the same way in every run.
+///
+/// Panics if one of the configs selects an unknown strategy.
+pub fn play_games(
+    configs: &[AlgorithmConfig],
+    size: &Position,
+    games: usize,
+    seed: Option<u64>,
+) -> Vec<GameOutcome> {
+    let num_threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(games.max(1));
+    let mut outcomes: Vec<(usize, GameOutcome)> = Vec::with_capacity(games);
+    thread::scope(|s| {
+        let workers: Vec<_> = (0..num_threads)
+            .map(|worker| {
+                s.spawn(move || {
+                    (worker..games)
+                        .step_by(num_threads)
+                        .map(|i| {
+                            let seed = match seed {
+                                Some(seed) => seed.wrapping_add(i as u64),
+                                None => rand::thread_rng().gen(),
+                            };
+                            debug!("Simulating game {} with seed {}", i, seed);
+                            (i, simulate_game(configs, size, seed))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for worker in workers {
+            outcomes.extend(worker.join().unwrap());
+        }
+    });
+    outcomes.sort_by_key(|(i, _outcome)| *i);
+    outcomes.into_iter().map(|(_i, outcome)| outcome).collect()
+}
+
+pub(crate) fn algorithm_configs(algorithms: &[String]) -> Vec<AlgorithmConfig> {
+    algorithms
+        .iter()
+        .map(|a| AlgorithmConfig {
+            algorithm: a.clone(),
+            ..AlgorithmConfig::default()
+        })
+        .collect()
+}
+
+/// Play a single game between the given strategies until at most one bot is left. The start
+/// positions and all random choices of the strategies are drawn from a random number generator
+/// seeded with `seed`, so a game is played the same way for the same seed.
+///
+/// Panics if one of the configs selects an unknown strategy.
+pub fn simulate_game(configs: &[AlgorithmConfig], size: &Position, seed: u64) -> GameOutcome {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let players: Vec<PlayerId> = (0..configs.len() as u32).map(PlayerId).collect();
+    let mut strategies: Vec<Box<dyn Strategy>> = configs
+        .iter()
+        .map(|config| create_strategy(config).unwrap())
+        .collect();
+    let mut game = Game::new(size.clone(), &players, &mut rng);
+    let mut states: Vec<State> = players
+        .iter()
+        .map(|player| {
+            let mut state = State::default();
+            state.update_from_answer(&Answer::Game(size.clone(), *player));
+            state
+        })
+        .collect();
+    for (strategy, state) in strategies.iter_mut().zip(states.iter()) {
+        strategy.init(state);
+    }
+    let mut ticks_survived = vec![0; players.len()];
+    let mut answers: Vec<Answer> = game
+        .alive_players()
+        .iter()
+        .map(|p| Answer::Pos(*p, game.heads()[p].clone()))
+        .collect();
+
+    let mut tick = 0;
+    while game.alive_players().len() > 1 {
+        answers.push(Answer::Tick);
+        for ((state, strategy), player) in states
+            .iter_mut()
+            .zip(strategies.iter_mut())
+            .zip(players.iter())
+        {
+            for answer in answers.iter() {
+                state.update_from_answer(answer);
+            }
+            if !game.is_alive(*player) {
+                continue;
+            }
+            if let Some(direction) =
+                decide_action(state, &mut rng, strategy.as_mut(), &Deadline::none()).direction
+            {
+                game.set_direction(*player, direction);
+            }
+        }
+        answers.clear();
+
+        let dead = game.step();
+        tick += 1;
+        for player in game.alive_players() {
+            ticks_survived[player.0 as usize] = tick;
+        }
+        for player in dead.iter() {
+            strategies[player.0 as usize].on_death(&states[player.0 as usize]);
+        }
+        if !dead.is_empty() {
+            answers.push(Answer::Die(dead));
+        }
+        answers.extend(
+            game.alive_players()
+                .iter()
+                .map(|p| Answer::Pos(*p, game.heads()[p].clone())),
+        );
+    }
+
+    let winner = game.alive_players().first().map(|p| p.0 as usize);
+    for (i, (strategy, state)) in strategies.iter_mut().zip(states.iter()).enumerate() {
+        strategy.on_game_end(state, winner == Some(i));
+    }
+    GameOutcome {
+        seed,
+        winner,
+        ticks_survived,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_plays_the_same_game() {
+        let configs = algorithm_configs(&["ranked".to_owned(), "flood".to_owned()]);
+        let size = default_size(configs.len());
+        for seed in 0..4 {
+            let first = simulate_game(&configs, &size, seed);
+            let second = simulate_game(&configs, &size, seed);
+            assert_eq!(first.winner, second.winner);
+            assert_eq!(first.ticks_survived, second.ticks_survived);
+        }
+    }
+}
@@ -0,0 +1,567 @@
+//! Tournaments between configured bot variants with Elo ratings.
+//!
+//! The variants play round-robin or Swiss matches in headless games of the simulator. Every game
+//! counts as a pairwise result between all bots in it: the winner beats everybody, and among the
+//! others the longer surviving bot wins. The ratings are the maximum likelihood estimate of the
+//! Bradley-Terry model on the Elo scale over all games, so they do not depend on the order of the
+//! games, and their confidence intervals come from bootstrapping the games.
+
+use crate::algorithm::{create_strategy, UnknownStrategy};
+use crate::simulator::{default_size, play_games, GameOutcome};
+use crate::{AlgorithmConfig, Position};
+use ordered_float::OrderedFloat;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+
+/// Number of resampled tournaments for the confidence intervals
+const BOOTSTRAP_SAMPLES: usize = 200;
+/// Rating of the average variant
+const MEAN_RATING: f64 = 1500.0;
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// Every combination of variants plays in every round
+    #[default]
+    RoundRobin,
+    /// Variants with similar ratings play each other in every round
+    Swiss,
+}
+
+#[derive(Deserialize)]
+pub struct VariantConfig {
+    pub name: String,
+    /// Strategy and parameters, like the `algorithm` section of a bot config
+    #[serde(flatten)]
+    pub algorithm: AlgorithmConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TournamentConfig {
+    pub format: Format,
+    pub rounds: usize,
+    /// Games per match
+    pub games: usize,
+    /// Number of bots per game. Every round has matches with each of them.
+    pub player_counts: Vec<usize>,
+    /// Edge lengths of the square boards the games of a match are spread over. If empty, the board
+    /// size scales with the number of players like on the local server.
+    pub sizes: Vec<u32>,
+    /// Seed of the pairings and games. Without a seed, every run differs.
+    pub seed: Option<u64>,
+    pub variants: Vec<VariantConfig>,
+}
+
+impl Default for TournamentConfig {
+    fn default() -> Self {
+        TournamentConfig {
+            format: Format::default(),
+            rounds: 1,
+            games: 20,
+            player_counts: vec![2],
+            sizes: Vec::new(),
+            seed: None,
+            variants: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TournamentError {
+    UnknownStrategy(UnknownStrategy),
+    /// Games with less than two players
+    TooFewPlayers {
+        players: usize,
+    },
+    /// More players per game than variants
+    TooFewVariants {
+        players: usize,
+        variants: usize,
+    },
+    /// A board without a field for every player of a game
+    BoardTooSmall {
+        size: u32,
+        players: usize,
+    },
+}
+
+impl fmt::Display for TournamentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TournamentError::UnknownStrategy(e) => write!(f, "{}", e),
+            TournamentError::TooFewPlayers { players } => {
+                write!(f, "Games need at least 2 players, got {}", players)
+            }
+            TournamentError::TooFewVariants { players, variants } => write!(
+                f,
+                "Games with {} players need at least {} variants, got {}",
+                players, players, variants
+            ),
+            TournamentError::BoardTooSmall { size, players } => write!(
+                f,
+                "A board of size {} is too small for games with {} players",
+                size, players
+            ),
+        }
+    }
+}
+
+impl Error for TournamentError {}
+
+/// A finished game: the variant of each seat and the outcome
+struct GameRecord {
+    variants: Vec<usize>,
+    outcome: GameOutcome,
+}
+
+impl GameRecord {
+    /// Score of the first against the second seat: 1 for a win, 0.5 for a draw
+    fn score(&self, a: usize, b: usize) -> f64 {
+        if self.outcome.winner == Some(a) {
+            return 1.0;
+        }
+        if self.outcome.winner == Some(b) {
+            return 0.0;
+        }
+        let (a, b) = (
+            self.outcome.ticks_survived[a],
+            self.outcome.ticks_survived[b],
+        );
+        if a > b {
+            1.0
+        } else if a < b {
+            0.0
+        } else {
+            0.5
+        }
+    }
+}
+
+/// Standing of a variant at the end of the tournament
+pub struct Standing {
+    pub name: String,
+    pub rating: f64,
+    /// 95% confidence interval of the rating
+    pub interval: (f64, f64),
+    pub games: usize,
+    pub wins: usize,
+    pub ticks_survived: usize,
+}
+
+impl Standing {
+    pub fn win_rate(&self) -> f64 {
+        self.wins as f64 / self.games.max(1) as f64
+    }
+
+    pub fn average_survival(&self) -> f64 {
+        self.ticks_survived as f64 / self.games.max(1) as f64
+    }
+}
+
+pub struct TournamentResult {
+    pub games: usize,
+    /// Standings of all variants, best rating first
+    pub standings: Vec<Standing>,
+}
+
+impl TournamentResult {
+    pub fn to_csv(&self) -> String {
+        let mut csv = "rank,variant,rating,rating_low,rating_high,games,wins,win_rate,\
+                       avg_ticks_survived\n"
+            .to_owned();
+        for (rank, s) in self.standings.iter().enumerate() {
+            writeln!(
+                csv,
+                "{},{},{:.1},{:.1},{:.1},{},{},{:.4},{:.2}",
+                rank + 1,
+                s.name,
+                s.rating,
+                s.interval.0,
+                s.interval.1,
+                s.games,
+                s.wins,
+                s.win_rate(),
+                s.average_survival()
+            )
+            .unwrap();
+        }
+        csv
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = "| rank | variant | Elo | 95% interval | games | wins | win rate | \
+                            avg. ticks survived |\n\
+                            |---:|---|---:|---:|---:|---:|---:|---:|\n"
+            .to_owned();
+        for (rank, s) in self.standings.iter().enumerate() {
+            writeln!(
+                markdown,
+                "| {} | {} | {:.0} | {:.0} – {:.0} | {} | {} | {:.1}% | {:.1} |",
+                rank + 1,
+                s.name,
+                s.rating,
+                s.interval.0,
+                s.interval.1,
+                s.games,
+                s.wins,
+                s.win_rate() * 100.0,
+                s.average_survival()
+            )
+            .unwrap();
+        }
+        markdown
+    }
+}
+
+/// Ratings of all variants on the Elo scale, with the average variant at [MEAN_RATING]
+///
+/// Fitted with the minorization-maximization algorithm for the Bradley-Terry model, counting a
+/// draw as half a win for both. Every variant gets a virtual draw against an average opponent, so
+/// that a variant which never won or never lost still gets a finite rating.
+fn fit_ratings(games: &[&GameRecord], variants: usize) -> Vec<f64> {
+    // Number of comparisons and points of every pair of variants
+    let mut comparisons = vec![vec![0.0; variants]; variants];
+    let mut points = vec![0.5; variants];
+    for game in games {
+        for a in 0..game.variants.len() {
+            for b in 0..game.variants.len() {
+                let (i, j) = (game.variants[a], game.variants[b]);
+                if i != j {
+                    comparisons[i][j] += 1.0;
+                    points[i] += game.score(a, b);
+                }
+            }
+        }
+    }
+
+    let mut strengths = vec![1.0; variants];
+    for _ in 0..1000 {
+        let mut change: f64 = 0.0;
+        for i in 0..variants {
+            let mut denominator = 1.0 / (strengths[i] + 1.0);
+            for j in 0..variants {
+                if comparisons[i][j] > 0.0 {
+                    denominator += comparisons[i][j] / (strengths[i] + strengths[j]);
+                }
+            }
+            let strength = points[i] / denominator;
+            change = change.max((strength / strengths[i]).ln().abs());
+            strengths[i] = strength;
+        }
+        if change < 1e-9 {
+            break;
+        }
+    }
+
+    let ratings: Vec<f64> = strengths.iter().map(|s| 400.0 * s.log10()).collect();
+    let mean = ratings.iter().sum::<f64>() / variants as f64;
+    ratings.iter().map(|r| r - mean + MEAN_RATING).collect()
+}
+
+/// The groups of variants playing a match with `players` bots in a round
+fn pairings(
+    format: Format,
+    players: usize,
+    ratings: &[f64],
+    rng: &mut impl Rng,
+) -> Vec<Vec<usize>> {
+    match format {
+        Format::RoundRobin => combinations(ratings.len(), players),
+        Format::Swiss => {
+            // Shuffle first, so that equal ratings, e.g. in the first round, are paired randomly
+            let mut order: Vec<usize> = (0..ratings.len()).collect();
+            order.shuffle(rng);
+            order.sort_by_key(|i| OrderedFloat(-ratings[*i]));
+            // A single variant left over gets a bye
+            order
+                .chunks(players)
+                .filter(|group| group.len() >= 2)
+                .map(|group| group.to_vec())
+                .collect()
+        }
+    }
+}
+
+/// All subsets of `k` out of `n` elements
+fn combinations(n: usize, k: usize) -> Vec<Vec<usize>> {
+    let mut result = Vec::new();
+    let mut current = Vec::with_capacity(k);
+    fn extend(
+        start: usize,
+        n: usize,
+        k: usize,
+        current: &mut Vec<usize>,
+        result: &mut Vec<Vec<usize>>,
+    ) {
+        if current.len() == k {
+            result.push(current.clone());
+            return;
+        }
+        for i in start..n {
+            current.push(i);
+            extend(i + 1, n, k, current, result);
+            current.pop();
+        }
+    }
+    extend(0, n, k, &mut current, &mut result);
+    result
+}
+
+/// Run the tournament, calling `progress` with the number of finished rounds after every round.
+pub fn run_tournament(
+    config: &TournamentConfig,
+    mut progress: impl FnMut(usize),
+) -> Result<TournamentResult, TournamentError> {
+    for variant in config.variants.iter() {
+        create_strategy(&variant.algorithm).map_err(TournamentError::UnknownStrategy)?;
+    }
+    let variants = config.variants.len();
+    for players in config.player_counts.iter().copied() {
+        if players < 2 {
+            return Err(TournamentError::TooFewPlayers { players });
+        }
+        if players > variants {
+            return Err(TournamentError::TooFewVariants { players, variants });
+        }
+        for size in config.sizes.iter().copied() {
+            if (size as usize).saturating_mul(size as usize) < players {
+                return Err(TournamentError::BoardTooSmall { size, players });
+            }
+        }
+    }
+    let mut rng = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(rand::thread_rng()).unwrap(),
+    };
+
+    let mut games: Vec<GameRecord> = Vec::new();
+    for round in 0..config.rounds {
+        let ratings = fit_ratings(&games.iter().collect::<Vec<_>>(), variants);
+        for players in config.player_counts.iter().copied() {
+            for group in pairings(config.format, players, &ratings, &mut rng) {
+                let configs: Vec<AlgorithmConfig> = group
+                    .iter()
+                    .map(|i| config.variants[*i].algorithm.clone())
+                    .collect();
+                let sizes: Vec<Position> = if config.sizes.is_empty() {
+                    vec![default_size(group.len())]
+                } else {
+                    config
+                        .sizes
+                        .iter()
+                        .map(|size| Position { x: *size, y: *size })
+                        .collect()
+                };
+                for (j, size) in sizes.iter().enumerate() {
+                    let count =
+                        config.games / sizes.len() + usize::from(j < config.games % sizes.len());
+                    for outcome in play_games(&configs, size, count, Some(rng.gen())) {
+                        games.push(GameRecord {
+                            variants: group.clone(),
+                            outcome,
+                        });
+                    }
+                }
+            }
+        }
+        progress(round + 1);
+    }
+
+    let all: Vec<&GameRecord> = games.iter().collect();
+    let ratings = fit_ratings(&all, variants);
+    let mut samples: Vec<Vec<f64>> = vec![Vec::with_capacity(BOOTSTRAP_SAMPLES); variants];
+    for _ in 0..BOOTSTRAP_SAMPLES {
+        let resampled: Vec<&GameRecord> = (0..games.len())
+            .map(|_| &games[rng.gen_range(0..games.len())])
+            .collect();
+        for (i, rating) in fit_ratings(&resampled, variants).into_iter().enumerate() {
+            samples[i].push(rating);
+        }
+    }
+
+    let mut standings: Vec<Standing> = config
+        .variants
+        .iter()
+        .enumerate()
+        .map(|(i, variant)| {
+            let samples = &mut samples[i];
+            samples.sort_by_key(|r| OrderedFloat(*r));
+            let percentile = |p: f64| samples[((samples.len() - 1) as f64 * p).round() as usize];
+            let mut standing = Standing {
+                name: variant.name.clone(),
+                rating: ratings[i],
+                interval: (percentile(0.025), percentile(0.975)),
+                games: 0,
+                wins: 0,
+                ticks_survived: 0,
+            };
+            for game in games.iter() {
+                for (seat, _variant) in game
+                    .variants
+                    .iter()
+                    .enumerate()
+                    .filter(|(_seat, v)| **v == i)
+                {
+                    standing.games += 1;
+                    standing.ticks_survived += game.outcome.ticks_survived[seat];
+                    if game.outcome.winner == Some(seat) {
+                        standing.wins += 1;
+                    }
+                }
+            }
+            standing
+        })
+        .collect();
+    standings.sort_by_key(|s| OrderedFloat(-s.rating));
+    Ok(TournamentResult {
+        games: games.len(),
+        standings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(sizes: Vec<u32>) -> TournamentConfig {
+        let variant = |name: &str| VariantConfig {
+            name: name.to_owned(),
+            algorithm: AlgorithmConfig::default(),
+        };
+        TournamentConfig {
+            games: 1,
+            player_counts: vec![2, 3],
+            sizes,
+            variants: vec![variant("a"), variant("b"), variant("c")],
+            ..TournamentConfig::default()
+        }
+    }
+
+    #[test]
+    fn rejects_boards_too_small_for_the_players() {
+        for size in [0, 1] {
+            match run_tournament(&config(vec![5, size]), |_| {}) {
+                Err(TournamentError::BoardTooSmall { size: s, players }) => {
+                    assert_eq!((s, players), (size, 2))
+                }
+                _ => panic!("Board of size {} accepted", size),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_games_with_less_than_two_players() {
+        for players in [0, 1] {
+            let config = TournamentConfig {
+                player_counts: vec![2, players],
+                ..config(Vec::new())
+            };
+            match run_tournament(&config, |_| {}) {
+                Err(TournamentError::TooFewPlayers { players: p }) => assert_eq!(p, players),
+                _ => panic!("Games with {} players accepted", players),
+            }
+        }
+    }
+
+    /// A game in which the bots die in the order of the seats from last to first
+    fn game(variants: Vec<usize>) -> GameRecord {
+        let ticks_survived = (0..variants.len()).rev().collect();
+        GameRecord {
+            variants,
+            outcome: GameOutcome {
+                seed: 0,
+                winner: Some(0),
+                ticks_survived,
+            },
+        }
+    }
+
+    #[test]
+    fn ratings_follow_the_results_and_average_to_the_mean() {
+        let games = [
+            game(vec![0, 1]),
+            game(vec![0, 2]),
+            game(vec![1, 2]),
+            game(vec![0, 1, 2]),
+        ];
+        let ratings = fit_ratings(&games.iter().collect::<Vec<_>>(), 3);
+        assert!(ratings[0] > ratings[1], "{:?}", ratings);
+        assert!(ratings[1] > ratings[2], "{:?}", ratings);
+        let mean = ratings.iter().sum::<f64>() / 3.0;
+        assert!((mean - MEAN_RATING).abs() < 1e-6, "{:?}", ratings);
+    }
+
+    #[test]
+    fn ratings_without_games_are_the_mean() {
+        assert_eq!(fit_ratings(&[], 2), vec![MEAN_RATING; 2]);
+    }
+
+    #[test]
+    fn combinations_are_all_subsets() {
+        assert_eq!(
+            combinations(4, 2),
+            vec![
+                vec![0, 1],
+                vec![0, 2],
+                vec![0, 3],
+                vec![1, 2],
+                vec![1, 3],
+                vec![2, 3]
+            ]
+        );
+        assert_eq!(combinations(3, 3), vec![vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn swiss_pairs_similar_ratings_and_gives_a_bye() {
+        let ratings = [1400.0, 1600.0, 1500.0, 1550.0, 1450.0];
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(
+            pairings(Format::Swiss, 2, &ratings, &mut rng),
+            vec![vec![1, 3], vec![2, 4]]
+        );
+        assert_eq!(
+            pairings(Format::RoundRobin, 3, &ratings, &mut rng),
+            combinations(5, 3)
+        );
+    }
+
+    fn result() -> TournamentResult {
+        TournamentResult {
+            games: 4,
+            standings: vec![Standing {
+                name: "flood".to_owned(),
+                rating: 1523.44,
+                interval: (1480.0, 1571.26),
+                games: 4,
+                wins: 3,
+                ticks_survived: 10,
+            }],
+        }
+    }
+
+    #[test]
+    fn writes_csv() {
+        assert_eq!(
+            result().to_csv(),
+            "rank,variant,rating,rating_low,rating_high,games,wins,win_rate,avg_ticks_survived\n\
+             1,flood,1523.4,1480.0,1571.3,4,3,0.7500,2.50\n"
+        );
+    }
+
+    #[test]
+    fn writes_markdown() {
+        assert_eq!(
+            result().to_markdown(),
+            "| rank | variant | Elo | 95% interval | games | wins | win rate | avg. ticks survived |\n\
+             |---:|---|---:|---:|---:|---:|---:|---:|\n\
+             | 1 | flood | 1523 | 1480 – 1571 | 4 | 3 | 75.0% | 2.5 |\n"
+        );
+    }
+}
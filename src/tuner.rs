@@ -0,0 +1,445 @@
+//! Tuning of the strategy parameters with SPSA (simultaneous perturbation stochastic
+//! approximation) in self-play.
+//!
+//! The floating point parameters of the strategy's config section form the parameter vector. Every
+//! iteration perturbs all of them at once in a random direction, lets both perturbed parameter sets
+//! play against each other and the opponents in headless games, and moves the parameters towards
+//! the better set. Both sets play the same games with swapped seats, so that the difference in
+//! their scores is not drowned by the luck of the start.
+
+use crate::algorithm::{create_strategy, UnknownStrategy};
+use crate::simulator::{algorithm_configs, default_size, play_games, GameOutcome};
+use crate::AlgorithmConfig;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Size of the perturbation in the first iteration, relative to the starting value of a parameter
+const PERTURBATION: f64 = 0.1;
+/// Size of the step per unit of the estimated gradient in the first iteration, relative to the
+/// starting value of a parameter
+const STEP: f64 = 0.1;
+/// Decay exponents of the step and the perturbation size recommended by Spall
+const STEP_DECAY: f64 = 0.602;
+const PERTURBATION_DECAY: f64 = 0.101;
+/// Bounds of a parameter as a multiple of its starting value. Keeps the parameters in a range where
+/// the strategies still behave sensibly, e.g. exponents do not change their sign.
+const MIN_FACTOR: f64 = 0.25;
+const MAX_FACTOR: f64 = 4.0;
+
+pub struct TuningConfig {
+    /// Strategy whose parameters are tuned
+    pub strategy: String,
+    /// Config the parameters start from, e.g. the algorithm section of a bot config
+    pub base: AlgorithmConfig,
+    pub iterations: usize,
+    /// Games per iteration. Half of them are played with swapped seats and the same seeds.
+    pub games: usize,
+    /// Strategy names of further bots taking part in every game
+    pub opponents: Vec<String>,
+    /// Games of the tuned against the starting parameters after the last iteration
+    pub evaluation_games: usize,
+    /// Seed of the perturbations and games. Runs with the same seed play the same games.
+    pub seed: u64,
+    /// File to save the progress to after every iteration and to resume from if it exists
+    pub checkpoint: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum TuningError {
+    UnknownStrategy(UnknownStrategy),
+    /// The strategy has no floating point parameters to tune
+    NoParameters(String),
+    /// The checkpoint was written for a different tuning run
+    CheckpointMismatch(PathBuf),
+}
+
+impl fmt::Display for TuningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TuningError::UnknownStrategy(e) => write!(f, "{}", e),
+            TuningError::NoParameters(strategy) => {
+                write!(f, "The {} strategy has no parameters to tune", strategy)
+            }
+            TuningError::CheckpointMismatch(path) => write!(
+                f,
+                "The checkpoint {} belongs to a different strategy or seed",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for TuningError {}
+
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    strategy: String,
+    seed: u64,
+    /// Number of finished iterations
+    iteration: usize,
+    parameters: BTreeMap<String, f64>,
+}
+
+/// Progress after an iteration
+pub struct Progress<'a> {
+    /// Number of finished iterations
+    pub iteration: usize,
+    /// Average score per game of the positively perturbed parameters minus the one of the
+    /// negatively perturbed parameters
+    pub score_difference: f64,
+    pub parameters: &'a [(String, f64)],
+}
+
+pub struct TuningResult {
+    pub strategy: String,
+    /// Tuned value of every parameter, by name
+    pub parameters: Vec<(String, f64)>,
+    /// Average score per game of the tuned and the starting parameters in the evaluation games
+    pub tuned_score: f64,
+    pub base_score: f64,
+    pub tuned_wins: usize,
+    pub base_wins: usize,
+    pub evaluation_games: usize,
+}
+
+impl TuningResult {
+    /// The tuned parameters as a section to paste into `config.toml`
+    pub fn config_section(&self) -> String {
+        let mut section = format!("[algorithm.{}]\n", config_section_name(&self.strategy));
+        for (name, value) in self.parameters.iter() {
+            let mut value = (*value as f32).to_string();
+            if !value.contains('.') {
+                value.push_str(".0");
+            }
+            section.push_str(&format!("{} = {}\n", name, value));
+        }
+        section
+    }
+}
+
+/// Name of the config section holding the parameters of a strategy
+fn config_section_name(strategy: &str) -> &str {
+    match strategy {
+        "chamber" => "ranked",
+        strategy => strategy,
+    }
+}
+
+/// The floating point parameters of the strategy's config section, by name
+fn parameters(config: &AlgorithmConfig, strategy: &str) -> Vec<(String, f64)> {
+    let value = serde_json::to_value(config).unwrap();
+    value
+        .get(config_section_name(strategy))
+        .and_then(|section| section.as_object())
+        .map(|section| {
+            section
+                .iter()
+                .filter(|(_name, value)| value.is_f64())
+                .map(|(name, value)| (name.clone(), value.as_f64().unwrap()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// `config` with the given parameters in the strategy's config section
+fn with_parameters(
+    config: &AlgorithmConfig,
+    strategy: &str,
+    parameters: &[(String, f64)],
+) -> AlgorithmConfig {
+    let mut value = serde_json::to_value(config).unwrap();
+    let section = value[config_section_name(strategy)]
+        .as_object_mut()
+        .unwrap();
+    for (name, parameter) in parameters {
+        section.insert(name.clone(), serde_json::json!(parameter));
+    }
+    serde_json::from_value(value).unwrap()
+}
+
+/// Score of every bot in a game: 1 for the winner, for everybody else up to 0.5 depending on how
+/// long they survived compared to the longest surviving bot
+fn scores(outcome: &GameOutcome) -> Vec<f64> {
+    let longest = outcome
+        .ticks_survived
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    outcome
+        .ticks_survived
+        .iter()
+        .enumerate()
+        .map(|(seat, ticks)| {
+            if outcome.winner == Some(seat) {
+                1.0
+            } else {
+                0.5 * *ticks as f64 / longest as f64
+            }
+        })
+        .collect()
+}
+
+/// Play `games` games between `first` and `second` and the opponents, half of them with swapped
+/// seats and the same seeds. Returns the total score and number of wins of both.
+fn play_match(
+    first: &AlgorithmConfig,
+    second: &AlgorithmConfig,
+    opponents: &[AlgorithmConfig],
+    games: usize,
+    seed: u64,
+) -> ([f64; 2], [usize; 2]) {
+    let size = default_size(opponents.len() + 2);
+    let mut score = [0.0; 2];
+    let mut wins = [0; 2];
+    for (swapped, games) in [(false, games.div_ceil(2)), (true, games / 2)]
+        .iter()
+        .copied()
+    {
+        let mut configs = if swapped {
+            vec![second.clone(), first.clone()]
+        } else {
+            vec![first.clone(), second.clone()]
+        };
+        configs.extend(opponents.iter().cloned());
+        for outcome in play_games(&configs, &size, games, Some(seed)) {
+            let scores = scores(&outcome);
+            for (i, seat) in [usize::from(swapped), usize::from(!swapped)]
+                .iter()
+                .copied()
+                .enumerate()
+            {
+                score[i] += scores[seat];
+                if outcome.winner == Some(seat) {
+                    wins[i] += 1;
+                }
+            }
+        }
+    }
+    (score, wins)
+}
+
+fn load_checkpoint(config: &TuningConfig) -> Result<Option<Checkpoint>, Box<dyn Error>> {
+    let path = match config.checkpoint.as_ref() {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let checkpoint: Checkpoint = serde_json::from_str(&content)?;
+    if checkpoint.strategy != config.strategy || checkpoint.seed != config.seed {
+        return Err(TuningError::CheckpointMismatch(path.clone()).into());
+    }
+    Ok(Some(checkpoint))
+}
+
+/// Tune the parameters of the strategy, calling `progress` after every iteration.
+pub fn tune(
+    config: &TuningConfig,
+    mut progress: impl FnMut(&Progress),
+) -> Result<TuningResult, Box<dyn Error>> {
+    let base = AlgorithmConfig {
+        algorithm: config.strategy.clone(),
+        ..config.base.clone()
+    };
+    create_strategy(&base).map_err(TuningError::UnknownStrategy)?;
+    let opponents = algorithm_configs(&config.opponents);
+    for opponent in opponents.iter() {
+        create_strategy(opponent).map_err(TuningError::UnknownStrategy)?;
+    }
+    let start = parameters(&base, &config.strategy);
+    if start.is_empty() {
+        return Err(TuningError::NoParameters(config.strategy.clone()).into());
+    }
+
+    // The parameters are tuned as multiples of their starting values, so that all of them move on
+    // the same scale and keep their sign
+    let scales: Vec<f64> = start
+        .iter()
+        .map(|(_name, value)| if *value == 0.0 { 1.0 } else { *value })
+        .collect();
+    let mut theta = vec![1.0; start.len()];
+    let mut first_iteration = 0;
+    if let Some(checkpoint) = load_checkpoint(config)? {
+        for ((i, (name, value)), scale) in start.iter().enumerate().zip(scales.iter()) {
+            if let Some(resumed) = checkpoint.parameters.get(name) {
+                theta[i] = 1.0 + (resumed - value) / scale;
+            }
+        }
+        first_iteration = checkpoint.iteration;
+    }
+    let values = |theta: &[f64]| -> Vec<(String, f64)> {
+        start
+            .iter()
+            .zip(scales.iter())
+            .zip(theta)
+            .map(|(((name, value), scale), x)| (name.clone(), value + (x - 1.0) * scale))
+            .collect()
+    };
+
+    let stability = config.iterations as f64 / 10.0;
+    for k in first_iteration..config.iterations {
+        // Every iteration has its own seed, so that a resumed run continues like an uninterrupted
+        // one
+        let mut rng = StdRng::seed_from_u64(config.seed.wrapping_add(k as u64));
+        let step = STEP / (k as f64 + 1.0 + stability).powf(STEP_DECAY);
+        let perturbation = PERTURBATION / (k as f64 + 1.0).powf(PERTURBATION_DECAY);
+        let delta: Vec<f64> = theta
+            .iter()
+            .map(|_| if rng.gen() { 1.0 } else { -1.0 })
+            .collect();
+        let perturbed = |sign: f64| {
+            let theta: Vec<f64> = theta
+                .iter()
+                .zip(delta.iter())
+                .map(|(x, d)| x + sign * perturbation * d)
+                .collect();
+            with_parameters(&base, &config.strategy, &values(&theta))
+        };
+        let (score, _wins) = play_match(
+            &perturbed(1.0),
+            &perturbed(-1.0),
+            &opponents,
+            config.games,
+            rng.gen(),
+        );
+        let score_difference = (score[0] - score[1]) / config.games.max(1) as f64;
+        for (x, d) in theta.iter_mut().zip(delta.iter()) {
+            let gradient = score_difference / (2.0 * perturbation * d);
+            *x = (*x + step * gradient).clamp(MIN_FACTOR, MAX_FACTOR);
+        }
+
+        let parameters = values(&theta);
+        if let Some(path) = config.checkpoint.as_ref() {
+            let checkpoint = Checkpoint {
+                strategy: config.strategy.clone(),
+                seed: config.seed,
+                iteration: k + 1,
+                parameters: parameters.iter().cloned().collect(),
+            };
+            fs::write(path, serde_json::to_string_pretty(&checkpoint)?)?;
+        }
+        progress(&Progress {
+            iteration: k + 1,
+            score_difference,
+            parameters: &parameters,
+        });
+    }
+
+    let parameters = values(&theta);
+    let tuned = with_parameters(&base, &config.strategy, &parameters);
+    let evaluation_seed = config.seed.wrapping_add(config.iterations as u64);
+    let (score, wins) = play_match(
+        &tuned,
+        &base,
+        &opponents,
+        config.evaluation_games,
+        evaluation_seed,
+    );
+    let games = config.evaluation_games.max(1) as f64;
+    Ok(TuningResult {
+        strategy: config.strategy.clone(),
+        parameters,
+        tuned_score: score[0] / games,
+        base_score: score[1] / games,
+        tuned_wins: wins[0],
+        base_wins: wins[1],
+        evaluation_games: config.evaluation_games,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameters_round_trip() {
+        let base = AlgorithmConfig::default();
+        let start = parameters(&base, "ranked");
+        assert!(!start.is_empty());
+        let doubled: Vec<(String, f64)> = start
+            .iter()
+            .map(|(name, value)| (name.clone(), value * 2.0))
+            .collect();
+        let config = with_parameters(&base, "ranked", &doubled);
+        assert_eq!(parameters(&config, "ranked"), doubled);
+        assert_eq!(parameters(&config, "flood"), parameters(&base, "flood"));
+    }
+
+    #[test]
+    fn config_section_is_valid_toml() {
+        let parameters = parameters(&AlgorithmConfig::default(), "chamber");
+        let result = TuningResult {
+            strategy: "chamber".to_owned(),
+            parameters: parameters.clone(),
+            tuned_score: 0.0,
+            base_score: 0.0,
+            tuned_wins: 0,
+            base_wins: 0,
+            evaluation_games: 0,
+        };
+        let value: toml::Value = toml::from_str(&result.config_section()).unwrap();
+        let section = value["algorithm"]["ranked"].as_table().unwrap();
+        assert_eq!(section.len(), parameters.len());
+        // The section is written with the precision of the config fields
+        for (name, parameter) in parameters {
+            assert_eq!(
+                section[&name].as_float().map(|value| value as f32),
+                Some(parameter as f32)
+            );
+        }
+    }
+
+    #[test]
+    fn resumed_run_matches_uninterrupted_run() {
+        let checkpoint =
+            std::env::temp_dir().join(format!("gpn21-tron-tuner-test-{}.json", std::process::id()));
+        let config = TuningConfig {
+            strategy: "ranked".to_owned(),
+            base: AlgorithmConfig::default(),
+            iterations: 3,
+            games: 2,
+            opponents: Vec::new(),
+            evaluation_games: 0,
+            seed: 7,
+            checkpoint: Some(checkpoint.clone()),
+        };
+        let mut after_first_iteration = None;
+        let uninterrupted = tune(&config, |progress| {
+            if progress.iteration == 1 {
+                after_first_iteration = Some(fs::read_to_string(&checkpoint).unwrap());
+            }
+        })
+        .unwrap();
+
+        fs::write(&checkpoint, after_first_iteration.unwrap()).unwrap();
+        let mut resumed_iterations = Vec::new();
+        let resumed = tune(&config, |progress| {
+            resumed_iterations.push(progress.iteration)
+        })
+        .unwrap();
+        fs::remove_file(&checkpoint).unwrap();
+
+        assert_eq!(resumed_iterations, vec![2, 3]);
+        assert_ne!(
+            uninterrupted.parameters,
+            parameters(&config.base, "ranked"),
+            "The games had no effect on the parameters"
+        );
+        for ((name, a), (other, b)) in uninterrupted.parameters.iter().zip(&resumed.parameters) {
+            assert_eq!(name, other);
+            assert!((a - b).abs() < 1e-9, "{}: {} != {}", name, a, b);
+        }
+    }
+}